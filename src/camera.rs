@@ -1,4 +1,4 @@
-use crate::ray::Ray;
+use crate::ray::{Differentials, Ray};
 use crate::vector::Vector3;
 
 #[derive(Default, Clone, Copy)]
@@ -25,15 +25,24 @@ impl Camera {
         self.xaxis = v.cross(up).normalize();
         self.yaxis = v.cross(self.xaxis);
 
-        let image_plane = (height as f64 / 2.0) / (fov as f64 / 2.0).tan();
+        let image_plane = (height as f64 / 2.0) / (fov / 2.0).tan();
         let center = v.scale(image_plane);
         self.origin =
             center - self.xaxis.scale(0.5 * width as f64) - self.yaxis.scale(0.5 * height as f64);
     }
 
-    pub fn ray(&self, x: f64, y: f64) -> Ray {
+    // footprint はサンプル一つが受け持つ範囲の幅 (ピクセル単位)
+    pub fn ray(&self, x: f64, y: f64, footprint: f64) -> Ray {
         let p = self.origin + self.xaxis.scale(x) + self.yaxis.scale(y);
         let dir = p.normalize();
-        Ray::new(self.eye, dir)
+
+        let differentials = Differentials {
+            rx_origin: self.eye,
+            rx_dir: (p + self.xaxis.scale(footprint)).normalize(),
+            ry_origin: self.eye,
+            ry_dir: (p + self.yaxis.scale(footprint)).normalize(),
+        };
+
+        Ray::new(self.eye, dir).with_differentials(Some(differentials))
     }
 }
@@ -1,14 +1,269 @@
+use crate::light_tree::LightBounds;
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::ray::{Differentials, Ray};
+use crate::texture::TexturePoint;
 use crate::vector::Vector3;
 
 pub trait Intersectable: Send + Sync {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection>;
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>>;
+
+    fn material(&self) -> &Material;
+
+    // 直接サンプリングできる光源なら、その範囲と明るさの見積もり
+    fn light_bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    // point から見える表面上の点を選び、立体角あたりの確率密度とともに返す
+    fn sample_light(&self, _point: Vector3) -> Option<(Intersection<'_>, f64)> {
+        None
+    }
+
+    // point から sample_light で x が選ばれる確率密度 (立体角あたり)
+    fn light_pdf(&self, _point: Vector3, _x: &Intersection) -> f64 {
+        0.0
+    }
 }
 
-pub struct Intersection {
+pub struct Intersection<'a> {
     pub distance: f64,
     pub point: Vector3,
-    pub normal: Vector3, // 法線
-    pub material: Material,
+    pub normal: Vector3,         // 法線
+    pub shading_normal: Vector3, // バンプマップなどで傾けた陰影計算用の法線
+    pub tangent: Vector3,        // 異方性反射の向き (shading_normal に直交する)
+    pub uv: (f64, f64),          // テクスチャ座標
+    pub dpdu: Vector3,
+    pub dpdv: Vector3,
+    pub dndu: Vector3,
+    pub dndv: Vector3,
+    pub material: &'a Material, // テクスチャを評価する前のもの
+    pub differentials: SurfaceDifferentials,
+}
+
+// 隣のピクセルに移ったときの交点と UV の変化量
+#[derive(Clone, Copy, Default)]
+pub struct SurfaceDifferentials {
+    pub dpdx: Vector3,
+    pub dpdy: Vector3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+impl Intersection<'_> {
+    pub fn compute_differentials(&mut self, ray: &Ray) {
+        self.differentials = SurfaceDifferentials::default();
+
+        let d = match ray.differentials {
+            Some(d) => d,
+            None => return,
+        };
+
+        // 微分レイと接平面の交点
+        let n = self.normal;
+        let p = self.point;
+        let hit = |origin: Vector3, dir: Vector3| {
+            let t = n.dot(&(p - origin)) / n.dot(&dir);
+            origin + dir.scale(t) - p
+        };
+        let dpdx = hit(d.rx_origin, d.rx_dir);
+        let dpdy = hit(d.ry_origin, d.ry_dir);
+
+        if !(dpdx.len().is_finite() && dpdy.len().is_finite()) {
+            return;
+        }
+
+        // 法線の成分が最も大きい軸を除いた 2 軸で dp = du * dpdu + dv * dpdv を解く
+        let c = |v: Vector3| [v.x, v.y, v.z];
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let (pu, pv) = (c(self.dpdu), c(self.dpdv));
+        let det = pu[a] * pv[b] - pv[a] * pu[b];
+
+        let solve = |dp: Vector3| {
+            let dp = c(dp);
+            if det.abs() < 1e-12 {
+                return (0.0, 0.0);
+            }
+            (
+                (pv[b] * dp[a] - pv[a] * dp[b]) / det,
+                (pu[a] * dp[b] - pu[b] * dp[a]) / det,
+            )
+        };
+        let (dudx, dvdx) = solve(dpdx);
+        let (dudy, dvdy) = solve(dpdy);
+
+        self.differentials = SurfaceDifferentials {
+            dpdx,
+            dpdy,
+            dudx,
+            dvdx,
+            dudy,
+            dvdy,
+        };
+    }
+
+    fn normal_differentials(&self, normal: Vector3) -> (Vector3, Vector3) {
+        let d = &self.differentials;
+        let dndx = self.dndu.scale(d.dudx) + self.dndv.scale(d.dvdx);
+        let dndy = self.dndu.scale(d.dudy) + self.dndv.scale(d.dvdy);
+
+        // 裏から当たって法線を反転している場合
+        if normal.dot(&self.normal) < 0.0 {
+            (-dndx, -dndy)
+        } else {
+            (dndx, dndy)
+        }
+    }
+
+    // 鏡面反射したレイの微分
+    pub fn reflect_differentials(
+        &self,
+        ray: &Ray,
+        normal: Vector3,
+        wi: Vector3,
+    ) -> Option<Differentials> {
+        let d = ray.differentials?;
+        let (dndx, dndy) = self.normal_differentials(normal);
+        let wo = -ray.dir;
+        let cos = wo.dot(&normal);
+
+        let dir = |rd: Vector3, dn: Vector3| {
+            let dwo = ray.dir - rd;
+            let ddn = dwo.dot(&normal) + wo.dot(&dn);
+            wi - dwo + (dn.scale(cos) + normal.scale(ddn)).scale(2.0)
+        };
+
+        Some(Differentials {
+            rx_origin: self.point + self.differentials.dpdx,
+            rx_dir: dir(d.rx_dir, dndx),
+            ry_origin: self.point + self.differentials.dpdy,
+            ry_dir: dir(d.ry_dir, dndy),
+        })
+    }
+
+    // 屈折したレイの微分 (eta は入射側 / 透過側の屈折率)
+    pub fn refract_differentials(
+        &self,
+        ray: &Ray,
+        normal: Vector3,
+        wi: Vector3,
+        eta: f64,
+    ) -> Option<Differentials> {
+        let d = ray.differentials?;
+        let (dndx, dndy) = self.normal_differentials(normal);
+        let wo = -ray.dir;
+        let cos_o = wo.dot(&normal);
+        let cos_i = wi.dot(&normal);
+        let mu = eta * cos_o + cos_i;
+
+        let dir = |rd: Vector3, dn: Vector3| {
+            let dwo = ray.dir - rd;
+            let ddn = dwo.dot(&normal) + wo.dot(&dn);
+            let dmu = (eta + eta * eta * cos_o / cos_i) * ddn;
+            wi - dwo.scale(eta) + normal.scale(dmu) + dn.scale(mu)
+        };
+
+        Some(Differentials {
+            rx_origin: self.point + self.differentials.dpdx,
+            rx_dir: dir(d.rx_dir, dndx),
+            ry_origin: self.point + self.differentials.dpdy,
+            ry_dir: dir(d.ry_dir, dndy),
+        })
+    }
+
+    // マテリアルの法線マップやバンプマップから陰影計算用の法線を求める
+    pub fn apply_normal_map(&mut self) {
+        let n = self.normal;
+        let p = self.texture_point();
+        let material = self.material;
+
+        let shading = if let Some(map) = &material.normal_map {
+            // 接空間の法線 (RGB を -1 から 1 に戻す)
+            let c = map.evaluate(&p);
+            let t = (self.dpdu - n.scale(n.dot(&self.dpdu))).normalize();
+            let b = n.cross(t);
+            (t.scale(2.0 * c.r - 1.0) + b.scale(2.0 * c.g - 1.0) + n.scale(2.0 * c.b - 1.0))
+                .normalize()
+        } else if let Some(bump) = &material.bump_texture {
+            // 高さの変化を有限差分で求めて接ベクトルを傾ける
+            let d = &self.differentials;
+            let delta = |a: f64, b: f64| {
+                let x = 0.5 * (a.abs() + b.abs());
+                if x > 0.0 {
+                    x
+                } else {
+                    1e-4
+                }
+            };
+            let du = delta(d.dudx, d.dudy);
+            let dv = delta(d.dvdx, d.dvdy);
+
+            let height = |dp: Vector3, du: f64, dv: f64| {
+                material.bump_height
+                    * bump.value(&TexturePoint {
+                        point: p.point + dp,
+                        u: p.u + du,
+                        v: p.v + dv,
+                        ..p
+                    })
+            };
+            let h = height(Vector3::default(), 0.0, 0.0);
+            let dhdu = (height(self.dpdu.scale(du), du, 0.0) - h) / du;
+            let dhdv = (height(self.dpdv.scale(dv), 0.0, dv) - h) / dv;
+
+            let dpdu = self.dpdu + n.scale(dhdu) + self.dndu.scale(h);
+            let dpdv = self.dpdv + n.scale(dhdv) + self.dndv.scale(h);
+            let bumped = dpdu.cross(dpdv).normalize();
+            if bumped.dot(&n) < 0.0 {
+                -bumped
+            } else {
+                bumped
+            }
+        } else {
+            n
+        };
+
+        // 接ベクトルが潰れている点 (球の極など) では元の法線を使う
+        self.shading_normal = if shading.len().is_finite() {
+            shading
+        } else {
+            n
+        };
+    }
+
+    // マテリアルで向きが指定されていなければ UV の u 方向を使う
+    pub fn compute_tangent(&mut self) {
+        let n = self.shading_normal;
+        let t = self.material.tangent.unwrap_or(self.dpdu);
+        let t = t - n.scale(n.dot(&t));
+
+        self.tangent = if t.len() > 1e-8 {
+            t.normalize()
+        } else {
+            n.orthonormal_basis().0
+        };
+    }
+
+    pub fn texture_point(&self) -> TexturePoint {
+        let d = &self.differentials;
+        TexturePoint {
+            point: self.point,
+            normal: self.normal,
+            u: self.uv.0,
+            v: self.uv.1,
+            dpdx: d.dpdx,
+            dpdy: d.dpdy,
+            dudx: d.dudx,
+            dvdx: d.dvdx,
+            dudy: d.dudy,
+            dvdy: d.dvdy,
+        }
+    }
 }
@@ -0,0 +1,242 @@
+use crate::random;
+use crate::vector::Vector3;
+
+// 光源を囲む箱と、放射する量の見積もり
+#[derive(Clone, Copy)]
+pub struct LightBounds {
+    pub min: Vector3,
+    pub max: Vector3,
+    pub power: f64,
+}
+
+impl LightBounds {
+    fn union(&self, o: &LightBounds) -> LightBounds {
+        LightBounds {
+            min: Vector3 {
+                x: self.min.x.min(o.min.x),
+                y: self.min.y.min(o.min.y),
+                z: self.min.z.min(o.min.z),
+            },
+            max: Vector3 {
+                x: self.max.x.max(o.max.x),
+                y: self.max.y.max(o.max.y),
+                z: self.max.z.max(o.max.z),
+            },
+            power: self.power + o.power,
+        }
+    }
+
+    fn centroid(&self) -> Vector3 {
+        (self.min + self.max).scale(0.5)
+    }
+
+    // point への寄与の見積もり。距離の二乗で減衰させ、箱の近くでは箱の大きさで抑える
+    fn importance(&self, point: Vector3) -> f64 {
+        let d = self.centroid() - point;
+        let half = (self.max - self.min).scale(0.5);
+        self.power / d.dot(&d).max(half.dot(&half)).max(1e-8)
+    }
+}
+
+struct Node {
+    bounds: LightBounds,
+    parent: Option<usize>,
+    children: Option<(usize, usize)>, // なければ葉
+    light: usize,                     // 葉の光源の番号
+}
+
+// 光源の BVH。根から寄与の見積もりに比例して子を選んでいき、光源を一つ選ぶ
+pub struct LightTree {
+    nodes: Vec<Node>,
+    leaves: Vec<usize>, // 光源の番号から葉のノード
+}
+
+impl LightTree {
+    pub fn new(lights: &[LightBounds]) -> Self {
+        let mut tree = Self {
+            nodes: vec![],
+            leaves: vec![0; lights.len()],
+        };
+
+        let mut items: Vec<(usize, LightBounds)> = lights.iter().copied().enumerate().collect();
+        if !items.is_empty() {
+            tree.build(&mut items, None);
+        }
+        tree
+    }
+
+    // 重心の広がりが最も大きい軸で、重心の中央値で二つに分ける
+    fn build(&mut self, items: &mut [(usize, LightBounds)], parent: Option<usize>) -> usize {
+        let index = self.nodes.len();
+        let bounds = items[1..].iter().fold(items[0].1, |b, (_, x)| b.union(x));
+        self.nodes.push(Node {
+            bounds,
+            parent,
+            children: None,
+            light: items[0].0,
+        });
+
+        if items.len() == 1 {
+            self.leaves[items[0].0] = index;
+            return index;
+        }
+
+        let (lo, hi) = items.iter().fold(
+            (items[0].1.centroid(), items[0].1.centroid()),
+            |(lo, hi), (_, x)| {
+                let c = x.centroid();
+                (
+                    Vector3 {
+                        x: lo.x.min(c.x),
+                        y: lo.y.min(c.y),
+                        z: lo.z.min(c.z),
+                    },
+                    Vector3 {
+                        x: hi.x.max(c.x),
+                        y: hi.y.max(c.y),
+                        z: hi.z.max(c.z),
+                    },
+                )
+            },
+        );
+        let extent = hi - lo;
+        let axis = |v: Vector3| {
+            if extent.x >= extent.y && extent.x >= extent.z {
+                v.x
+            } else if extent.y >= extent.z {
+                v.y
+            } else {
+                v.z
+            }
+        };
+        items.sort_by(|a, b| {
+            axis(a.1.centroid())
+                .partial_cmp(&axis(b.1.centroid()))
+                .unwrap()
+        });
+
+        let (left, right) = items.split_at_mut(items.len() / 2);
+        let left = self.build(left, Some(index));
+        let right = self.build(right, Some(index));
+        self.nodes[index].children = Some((left, right));
+        index
+    }
+
+    // point から見て左の子を選ぶ確率
+    fn left_probability(&self, left: usize, right: usize, point: Vector3) -> f64 {
+        let l = self.nodes[left].bounds.importance(point);
+        let r = self.nodes[right].bounds.importance(point);
+        if l + r <= 0.0 {
+            return 0.5;
+        }
+        l / (l + r)
+    }
+
+    // 光源の番号と、それが選ばれる確率
+    pub fn sample(&self, point: Vector3) -> Option<(usize, f64)> {
+        let mut node = self.nodes.first()?;
+        let mut pmf = 1.0;
+
+        while let Some((left, right)) = node.children {
+            let p = self.left_probability(left, right, point);
+            if random(0.0, 1.0) < p {
+                node = &self.nodes[left];
+                pmf *= p;
+            } else {
+                node = &self.nodes[right];
+                pmf *= 1.0 - p;
+            }
+        }
+
+        Some((node.light, pmf))
+    }
+
+    // point で sample したときに light が選ばれる確率
+    pub fn pmf(&self, point: Vector3, light: usize) -> f64 {
+        let mut index = self.leaves[light];
+        let mut pmf = 1.0;
+
+        while let Some(parent) = self.nodes[index].parent {
+            let (left, right) = self.nodes[parent].children.unwrap();
+            let p = self.left_probability(left, right, point);
+            pmf *= if index == left { p } else { 1.0 - p };
+            index = parent;
+        }
+
+        pmf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(x: f64, power: f64) -> LightBounds {
+        let p = Vector3 { x, y: 1.0, z: 0.0 };
+        let half = Vector3 {
+            x: 0.1,
+            y: 0.1,
+            z: 0.1,
+        };
+        LightBounds {
+            min: p - half,
+            max: p + half,
+            power,
+        }
+    }
+
+    fn lights() -> Vec<LightBounds> {
+        vec![
+            light(-4.0, 1.0),
+            light(-1.0, 3.0),
+            light(0.5, 0.5),
+            light(2.0, 2.0),
+            light(6.0, 10.0),
+        ]
+    }
+
+    const POINT: Vector3 = Vector3 {
+        x: 0.3,
+        y: 0.0,
+        z: 0.2,
+    };
+
+    #[test]
+    fn pmf_sums_to_one() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+        let total: f64 = (0..lights.len()).map(|i| tree.pmf(POINT, i)).sum();
+        assert!((total - 1.0).abs() < 1e-12, "{}", total);
+    }
+
+    #[test]
+    fn sample_agrees_with_pmf() {
+        let lights = lights();
+        let tree = LightTree::new(&lights);
+
+        let n = 200_000;
+        let mut counts = vec![0usize; lights.len()];
+        for _ in 0..n {
+            let (i, pmf) = tree.sample(POINT).unwrap();
+            assert!((pmf - tree.pmf(POINT, i)).abs() < 1e-12);
+            counts[i] += 1;
+        }
+
+        for (i, count) in counts.iter().enumerate() {
+            let expected = tree.pmf(POINT, i);
+            let actual = *count as f64 / n as f64;
+            assert!(
+                (actual - expected).abs() < 0.01,
+                "light {}: {} vs {}",
+                i,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn empty_tree_has_no_samples() {
+        assert!(LightTree::new(&[]).sample(POINT).is_none());
+    }
+}
@@ -1,25 +1,35 @@
 #![allow(dead_code)]
 
 mod camera;
-mod checked_obj;
 mod intersect;
 mod light;
+mod light_tree;
 mod material;
+mod medium;
+mod merl;
+mod mesh;
+mod microfacet;
+mod noise;
 mod plane;
+mod principled;
 mod ray;
+mod restir;
 mod scene;
 mod spectrum;
 mod sphere;
-mod textured_obj;
+mod texture;
+mod thin_film;
 mod vector;
+mod volume;
 
-use checked_obj::CheckedObject;
 use material::Material;
 use plane::Plane;
 use ray::Ray;
+use restir::{DirectReuse, Reservoir};
 use scene::Scene;
 use spectrum::Spectrum;
 use sphere::Sphere;
+use texture::Checker;
 use vector::Vector3;
 
 use sdl2::event::Event;
@@ -45,6 +55,7 @@ const SAMPLES: u32 = 500;
 const WORKERS: usize = 8;
 const GUI_SAMPLE_STEP: u32 = 50;
 const WORKERS_STEP: u32 = 4;
+const SPATIAL_RADIUS: u32 = 16;
 
 use once_cell::unsync::Lazy;
 
@@ -181,11 +192,14 @@ struct Drawer {
     width: u32,
     height: u32,
     samples: Mutex<u32>,
+    restir: bool,
+    reservoirs: Arc<Mutex<Vec<Reservoir>>>, // ReSTIR で各ピクセルが前のサンプルで残したもの
 }
 
 impl Drawer {
     fn new(width: u32, height: u32) -> Self {
         let mut scene = Scene::new();
+        scene.set_spectral(std::env::var("RAYTRACER_SPECTRAL").is_ok());
 
         scene.set_sky_color(Spectrum {
             r: 0.1,
@@ -243,6 +257,11 @@ impl Drawer {
                 },
                 refractive: 0.8,
                 refractive_index: 1.5,
+                absorption: Spectrum {
+                    r: 0.6,
+                    g: 0.05,
+                    b: 0.18,
+                },
                 ..Material::default()
             },
         });
@@ -269,37 +288,39 @@ impl Drawer {
             },
         });
 
-        scene.add_object(CheckedObject {
-            object: Plane::new(
-                Vector3 {
-                    x: 0.0,
-                    y: -1.0,
-                    z: 0.0,
-                },
-                Vector3 {
-                    x: 0.0,
-                    y: 1.0,
-                    z: 0.0,
+        scene.add_object(Plane::new(
+            Vector3 {
+                x: 0.0,
+                y: -1.0,
+                z: 0.0,
+            },
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            },
+            Material {
+                diffuse: Spectrum {
+                    r: 1.0,
+                    g: 1.0,
+                    b: 1.0,
                 },
-                Material {
-                    diffuse: Spectrum {
+                diffuse_texture: Some(Arc::new(Checker {
+                    even: Arc::new(Spectrum {
+                        r: 0.4,
+                        g: 0.4,
+                        b: 0.4,
+                    }),
+                    odd: Arc::new(Spectrum {
                         r: 0.9,
                         g: 0.9,
                         b: 0.9,
-                    },
-                    ..Material::default()
-                },
-            ),
-            grid_width: 1.0,
-            alt_material: Material {
-                diffuse: Spectrum {
-                    r: 0.4,
-                    g: 0.4,
-                    b: 0.4,
-                },
+                    }),
+                    grid_width: 1.0,
+                })),
                 ..Material::default()
             },
-        });
+        ));
 
         let mut camera = Camera::default();
         camera.look_at(
@@ -337,6 +358,11 @@ impl Drawer {
                 (width * height) as usize
             ])),
             samples: Mutex::new(0),
+            restir: std::env::var("RAYTRACER_RESTIR").is_ok(),
+            reservoirs: Arc::new(Mutex::new(vec![
+                Reservoir::default();
+                (width * height) as usize
+            ])),
         }
     }
 
@@ -352,6 +378,8 @@ impl Drawer {
             scene: Arc::clone(&self.scene),
             canvas: Arc::clone(&self.canvas),
             current_height: Arc::clone(&current_height),
+            restir: self.restir,
+            reservoirs: Arc::clone(&self.reservoirs),
         };
 
         {
@@ -397,12 +425,45 @@ struct Worker {
     scene: Arc<Scene>,
     canvas: Arc<Mutex<Vec<Spectrum>>>,
     current_height: Arc<Mutex<u32>>,
+    restir: bool,
+    reservoirs: Arc<Mutex<Vec<Reservoir>>>,
 }
 
 impl Worker {
-    fn calc_primary_ray(&self, x: f64, y: f64) -> Ray {
+    // ピクセルを samples 個のサンプルで分け合うので、微分もそれに合わせて狭める
+    fn calc_primary_ray(&self, x: f64, y: f64, samples: u32) -> Ray {
+        let footprint = (1.0 / (samples.max(1) as f64).sqrt()).max(0.125);
         self.camera
-            .ray(x + random(-0.5, 0.5), y + random(-0.5, 0.5))
+            .ray(x + random(-0.5, 0.5), y + random(-0.5, 0.5), footprint)
+    }
+
+    // 同じピクセルと、近くのいくつかのピクセルのリザーバ (rows は reservoirs に入っている行)
+    fn reuse(
+        &self,
+        reservoirs: &[Reservoir],
+        rows: &std::ops::Range<u32>,
+        x: u32,
+        y: u32,
+    ) -> DirectReuse {
+        let index = |x: u32, y: u32| ((y - rows.start) * self.canvas_width + x) as usize;
+        let mut neighbours = [Reservoir::default(); restir::SPATIAL_NEIGHBOURS];
+
+        let r = SPATIAL_RADIUS as f64;
+        for n in neighbours.iter_mut() {
+            let nx = (x as f64 + random(-r, r)).clamp(0.0, (self.canvas_width - 1) as f64) as u32;
+            let ny =
+                (y as f64 + random(-r, r)).clamp(rows.start as f64, (rows.end - 1) as f64) as u32;
+            // 同じピクセルは二回使わない
+            if (nx, ny) != (x, y) {
+                *n = reservoirs[index(nx, ny)];
+            }
+        }
+
+        DirectReuse {
+            previous: reservoirs[index(x, y)],
+            neighbours,
+            result: Reservoir::default(),
+        }
     }
 
     fn run(&self, samples: u32) {
@@ -421,19 +482,48 @@ impl Worker {
                 range
             };
 
+            // ReSTIR では前のサンプルのリザーバを、近傍を探す範囲の行まで含めて持ってくる
+            let rows = render_range.start.saturating_sub(SPATIAL_RADIUS)
+                ..(render_range.end + SPATIAL_RADIUS).min(self.canvas_height);
+            let mut reservoirs = if self.restir {
+                let width = self.canvas_width;
+                self.reservoirs.lock().unwrap()
+                    [(rows.start * width) as usize..(rows.end * width) as usize]
+                    .to_vec()
+            } else {
+                vec![]
+            };
+
             let mut results = Vec::with_capacity((self.canvas_height * WORKERS_STEP) as usize);
 
             for _ in 0..samples {
                 for y in render_range.clone() {
                     for x in 0..self.canvas_width {
-                        let primary_ray = self.calc_primary_ray(x as _, y as _);
-
-                        let result = self.scene.trace(primary_ray, 0);
+                        let primary_ray = self.calc_primary_ray(x as _, y as _, samples);
+
+                        let result = if self.restir {
+                            let reuse = RefCell::new(self.reuse(&reservoirs, &rows, x, y));
+                            let result = self.scene.trace(primary_ray, 0, Some(&reuse));
+                            reservoirs[((y - rows.start) * self.canvas_width + x) as usize] =
+                                reuse.into_inner().result;
+                            result
+                        } else {
+                            self.scene.trace(primary_ray, 0, None)
+                        };
                         results.push((((y * self.canvas_height) + x), result));
                     }
                 }
             }
 
+            if self.restir {
+                let width = self.canvas_width as usize;
+                let own = (render_range.start - rows.start) as usize * width
+                    ..(render_range.end - rows.start) as usize * width;
+                let start = render_range.start as usize * width;
+                let mut shared = self.reservoirs.lock().unwrap();
+                shared[start..start + own.len()].copy_from_slice(&reservoirs[own]);
+            }
+
             let mut canvas_lock = self.canvas.lock().unwrap();
             for (index, result) in results {
                 canvas_lock[index as usize] += result;
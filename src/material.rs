@@ -1,12 +1,50 @@
+use crate::medium::Medium;
+use crate::merl::MeasuredBrdf;
+use crate::principled::Principled;
+use crate::random;
 use crate::spectrum::{Spectrum, BLACK};
+use crate::texture::{Texture, TexturePoint};
+use crate::thin_film::ThinFilm;
+use crate::vector::Vector3;
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Material {
     pub diffuse: Spectrum,
     pub reflective: f64,
+    pub roughness: f64,           // 鏡面反射の粗さ (0 なら完全な鏡面)
+    pub anisotropy: f64,          // 0 なら等方的、1 に近いほど tangent 方向に伸びた反射になる
+    pub tangent: Option<Vector3>, // 異方性反射の向き (なければ UV の u 方向)
     pub refractive: f64,
     pub refractive_index: f64,
+    pub thin_walled: bool, // 屈折する面を厚みのない板として扱う (窓ガラスや泡)
     pub emissive: Spectrum,
+    pub opacity: f64,                   // 1 未満なら確率的に、0 なら完全に素通りする
+    pub absorption: Spectrum,           // 内部での吸収係数
+    pub medium: Option<Medium>,         // 内部の媒質
+    pub priority: u32,                  // 入れ子になった誘電体ではより高い方が優先される
+    pub dispersion: Option<Dispersion>, // スペクトルモードでは refractive_index の代わりに使う
+    pub bump_height: f64,               // バンプマップの値 1 あたりの高さ
+    pub principled: Option<Principled>, // あれば reflective と refractive の代わりに使う
+    pub conductor: Option<Conductor>,   // あれば鏡面反射の色を複素屈折率から求める
+    pub thin_film: Option<ThinFilm>,    // 金属か屈折する物体の表面の薄膜
+    pub coating: Option<Coating>,       // 表面を覆う透明な層
+    pub subsurface: Option<Subsurface>, // あれば内部で散乱する半透明な物体になる
+
+    // あれば他の反射の設定の代わりに測定データで反射する
+    pub measured: Option<Arc<MeasuredBrdf>>,
+
+    // 各チャンネルの値に掛け合わされるテクスチャ
+    pub diffuse_texture: Option<Arc<dyn Texture>>,
+    pub emissive_texture: Option<Arc<dyn Texture>>,
+    pub reflective_texture: Option<Arc<dyn Texture>>,
+    pub roughness_texture: Option<Arc<dyn Texture>>,
+    pub thin_film_texture: Option<Arc<dyn Texture>>, // 薄膜の厚さ
+    pub opacity_texture: Option<Arc<dyn Texture>>, // 画像のアルファを使うときは texture::Alpha で包む
+
+    // 陰影計算用の法線を傾けるテクスチャ (法線マップがあればそちらを使う)
+    pub bump_texture: Option<Arc<dyn Texture>>,
+    pub normal_map: Option<Arc<dyn Texture>>, // 接空間の法線を RGB に詰めたもの (リニアで読み込む)
 }
 
 impl Default for Material {
@@ -14,9 +52,474 @@ impl Default for Material {
         Material {
             diffuse: Spectrum::default(),
             reflective: 0.0,
+            roughness: 0.0,
+            anisotropy: 0.0,
+            tangent: None,
             refractive: 0.0,
             refractive_index: 1.0,
+            thin_walled: false,
             emissive: BLACK,
+            opacity: 1.0,
+            absorption: BLACK,
+            medium: None,
+            priority: 0,
+            dispersion: None,
+            bump_height: 1.0,
+            principled: None,
+            conductor: None,
+            thin_film: None,
+            coating: None,
+            subsurface: None,
+            measured: None,
+            diffuse_texture: None,
+            emissive_texture: None,
+            reflective_texture: None,
+            roughness_texture: None,
+            thin_film_texture: None,
+            opacity_texture: None,
+            bump_texture: None,
+            normal_map: None,
+        }
+    }
+}
+
+impl Material {
+    // 屈折する確率
+    pub fn transmission(&self) -> f64 {
+        if self.subsurface.is_some() {
+            return 1.0;
+        }
+        self.principled
+            .map_or(self.refractive, |p| p.transmission_weight())
+    }
+
+    // 屈折率 (principled なら specular から求める)
+    pub fn ior(&self) -> f64 {
+        self.principled.map_or(self.refractive_index, |p| p.ior())
+    }
+
+    // 内部の媒質
+    pub fn interior_medium(&self) -> Option<Medium> {
+        self.subsurface.map(|s| s.medium()).or(self.medium)
+    }
+
+    pub fn is_emissive(&self) -> bool {
+        self.emissive.average() > 0.0
+    }
+
+    pub fn opacity_at(&self, p: &TexturePoint) -> f64 {
+        match &self.opacity_texture {
+            Some(t) => self.opacity * t.value(p),
+            None => self.opacity,
         }
     }
+
+    // 不透明度に応じて確率的に、光がその点で止まるか (素通りしないか) を決める
+    pub fn stops_at(&self, p: &TexturePoint) -> bool {
+        let opacity = self.opacity_at(p);
+        opacity >= 1.0 || random(0.0, 1.0) < opacity
+    }
+
+    // evaluate で評価するテクスチャがあるか
+    pub fn has_textures(&self) -> bool {
+        self.diffuse_texture.is_some()
+            || self.emissive_texture.is_some()
+            || self.reflective_texture.is_some()
+            || self.roughness_texture.is_some()
+            || (self.thin_film.is_some() && self.thin_film_texture.is_some())
+    }
+
+    pub fn emissive_at(&self, p: &TexturePoint) -> Spectrum {
+        match &self.emissive_texture {
+            Some(t) => self.emissive * t.evaluate(p),
+            None => self.emissive,
+        }
+    }
+
+    // テクスチャをある点で評価して各チャンネルに反映したもの (テクスチャ自体は持たない)。
+    // 不透明度は交点を探すときに使い終わっているので評価しない
+    pub fn evaluate(&self, p: &TexturePoint) -> Material {
+        let color = |c: Spectrum, t: &Option<Arc<dyn Texture>>| match t {
+            Some(t) => c * t.evaluate(p),
+            None => c,
+        };
+        let value = |v: f64, t: &Option<Arc<dyn Texture>>| match t {
+            Some(t) => v * t.value(p),
+            None => v,
+        };
+
+        Material {
+            diffuse: color(self.diffuse, &self.diffuse_texture),
+            emissive: self.emissive_at(p),
+            reflective: value(self.reflective, &self.reflective_texture),
+            roughness: value(self.roughness, &self.roughness_texture),
+            thin_film: self.thin_film.map(|f| ThinFilm {
+                thickness: value(f.thickness, &self.thin_film_texture),
+                ..f
+            }),
+            measured: self.measured.clone(),
+            diffuse_texture: None,
+            emissive_texture: None,
+            reflective_texture: None,
+            roughness_texture: None,
+            thin_film_texture: None,
+            opacity_texture: None,
+            bump_texture: None,
+            normal_map: None,
+            ..*self
+        }
+    }
+
+    // ある波長での値だけを持つマテリアル
+    pub fn monochrome(self, wavelength: f64) -> Material {
+        Material {
+            diffuse: self.diffuse.monochrome(wavelength),
+            emissive: self.emissive.monochrome(wavelength),
+            absorption: self.absorption.monochrome(wavelength),
+            medium: self.medium.map(|m| m.monochrome(wavelength)),
+            subsurface: self.subsurface.map(|s| s.monochrome(wavelength)),
+            coating: self.coating.map(|c| Coating {
+                absorption: c.absorption.monochrome(wavelength),
+                ..c
+            }),
+            conductor: self.conductor.map(|c| c.monochrome(wavelength)),
+            refractive_index: self
+                .dispersion
+                .map_or(self.refractive_index, |d| d.refractive_index(wavelength)),
+            ..self
+        }
+    }
+}
+
+// 屈折率の波長依存性 (波長はマイクロメートル)
+#[derive(Clone, Copy)]
+pub enum Dispersion {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [4.3356, 0.3306, 0.0],
+        c: [0.011_236, 0.030_625, 0.0],
+    };
+
+    pub fn refractive_index(&self, wavelength: f64) -> f64 {
+        let l = wavelength * 1e-3;
+        let l2 = l * l;
+
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0
+                    + b.iter()
+                        .zip(c.iter())
+                        .map(|(b, c)| b * l2 / (l2 - c))
+                        .sum::<f64>();
+                n2.sqrt()
+            }
+        }
+    }
+}
+
+// 金属の複素屈折率 η + ik (RGB の各チャンネルの代表波長での値)
+#[derive(Clone, Copy)]
+pub struct Conductor {
+    pub eta: Spectrum,
+    pub k: Spectrum,
+    pub table: Option<&'static [(f64, f64, f64)]>, // スペクトルモードで使う (波長 [nm], η, k) の表
+}
+
+// Johnson & Christy (1972) と Rakić (1995) の測定値を間引いたもの
+const GOLD_TABLE: [(f64, f64, f64); 14] = [
+    (381.0, 1.46, 1.933),
+    (397.0, 1.47, 1.952),
+    (413.0, 1.46, 1.958),
+    (430.0, 1.45, 1.948),
+    (451.0, 1.38, 1.914),
+    (471.0, 1.31, 1.849),
+    (496.0, 1.04, 1.833),
+    (521.0, 0.62, 2.081),
+    (549.0, 0.43, 2.455),
+    (582.0, 0.29, 2.863),
+    (617.0, 0.21, 3.272),
+    (659.0, 0.14, 3.697),
+    (704.0, 0.13, 4.103),
+    (756.0, 0.14, 4.542),
+];
+
+const SILVER_TABLE: [(f64, f64, f64); 14] = [
+    (381.0, 0.05, 1.864),
+    (397.0, 0.05, 2.070),
+    (413.0, 0.05, 2.275),
+    (430.0, 0.04, 2.462),
+    (451.0, 0.04, 2.657),
+    (471.0, 0.05, 2.869),
+    (496.0, 0.05, 3.093),
+    (521.0, 0.05, 3.324),
+    (549.0, 0.06, 3.586),
+    (582.0, 0.05, 3.858),
+    (617.0, 0.06, 4.152),
+    (659.0, 0.05, 4.483),
+    (704.0, 0.04, 4.838),
+    (756.0, 0.03, 5.242),
+];
+
+const COPPER_TABLE: [(f64, f64, f64); 14] = [
+    (381.0, 1.28, 2.21),
+    (397.0, 1.25, 2.26),
+    (413.0, 1.24, 2.32),
+    (430.0, 1.25, 2.40),
+    (451.0, 1.24, 2.43),
+    (471.0, 1.22, 2.51),
+    (496.0, 1.18, 2.59),
+    (521.0, 1.12, 2.60),
+    (549.0, 1.02, 2.58),
+    (582.0, 0.47, 2.81),
+    (617.0, 0.21, 3.21),
+    (659.0, 0.22, 3.75),
+    (704.0, 0.21, 4.21),
+    (756.0, 0.24, 4.67),
+];
+
+const ALUMINIUM_TABLE: [(f64, f64, f64); 9] = [
+    (380.0, 0.45, 4.60),
+    (400.0, 0.49, 4.86),
+    (450.0, 0.62, 5.47),
+    (500.0, 0.77, 6.08),
+    (550.0, 0.96, 6.69),
+    (600.0, 1.20, 7.26),
+    (650.0, 1.47, 7.79),
+    (700.0, 1.83, 8.31),
+    (750.0, 2.40, 8.62),
+];
+
+impl Conductor {
+    pub const GOLD: Conductor = Conductor {
+        eta: Spectrum {
+            r: 0.143,
+            g: 0.374,
+            b: 1.442,
+        },
+        k: Spectrum {
+            r: 3.983,
+            g: 2.385,
+            b: 1.603,
+        },
+        table: Some(&GOLD_TABLE),
+    };
+
+    pub const SILVER: Conductor = Conductor {
+        eta: Spectrum {
+            r: 0.155,
+            g: 0.117,
+            b: 0.138,
+        },
+        k: Spectrum {
+            r: 4.828,
+            g: 3.122,
+            b: 2.147,
+        },
+        table: Some(&SILVER_TABLE),
+    };
+
+    pub const COPPER: Conductor = Conductor {
+        eta: Spectrum {
+            r: 0.200,
+            g: 0.924,
+            b: 1.102,
+        },
+        k: Spectrum {
+            r: 3.912,
+            g: 2.452,
+            b: 2.142,
+        },
+        table: Some(&COPPER_TABLE),
+    };
+
+    pub const ALUMINIUM: Conductor = Conductor {
+        eta: Spectrum {
+            r: 1.657,
+            g: 0.880,
+            b: 0.521,
+        },
+        k: Spectrum {
+            r: 9.224,
+            g: 6.270,
+            b: 4.837,
+        },
+        table: Some(&ALUMINIUM_TABLE),
+    };
+
+    // 表があればその波長での値を線形補間し、なければ RGB の値から求める
+    pub fn monochrome(&self, wavelength: f64) -> Conductor {
+        let table = match self.table {
+            Some(t) if !t.is_empty() => t,
+            _ => {
+                return Conductor {
+                    eta: self.eta.monochrome(wavelength),
+                    k: self.k.monochrome(wavelength),
+                    ..*self
+                }
+            }
+        };
+
+        let i = table.partition_point(|x| x.0 < wavelength);
+        let (eta, k) = if i == 0 {
+            (table[0].1, table[0].2)
+        } else if i == table.len() {
+            let (_, eta, k) = table[i - 1];
+            (eta, k)
+        } else {
+            let ((w0, eta0, k0), (w1, eta1, k1)) = (table[i - 1], table[i]);
+            let t = (wavelength - w0) / (w1 - w0);
+            (eta0 + (eta1 - eta0) * t, k0 + (k1 - k0) * t)
+        };
+
+        Conductor {
+            eta: Spectrum {
+                r: eta,
+                g: eta,
+                b: eta,
+            },
+            k: Spectrum { r: k, g: k, b: k },
+            ..*self
+        }
+    }
+
+    // 屈折率 outside の媒質から入射したときのフレネル反射率
+    pub fn fresnel(&self, cos: f64, outside: f64) -> Spectrum {
+        let cos = cos.clamp(0.0, 1.0);
+        let cos2 = cos * cos;
+        let sin2 = 1.0 - cos2;
+
+        let channel = |eta: f64, k: f64| {
+            let (eta, k) = (eta / outside, k / outside);
+            let (eta2, k2) = (eta * eta, k * k);
+
+            let t0 = eta2 - k2 - sin2;
+            let a2b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+            let t1 = a2b2 + cos2;
+            let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+            let t2 = 2.0 * cos * a;
+            let rs = (t1 - t2) / (t1 + t2);
+
+            let t3 = cos2 * a2b2 + sin2 * sin2;
+            let t4 = t2 * sin2;
+            let rp = rs * (t3 - t4) / (t3 + t4);
+
+            0.5 * (rs + rp)
+        };
+
+        Spectrum {
+            r: channel(self.eta.r, self.k.r),
+            g: channel(self.eta.g, self.k.g),
+            b: channel(self.eta.b, self.k.b),
+        }
+    }
+}
+
+// 誘電体のフレネル反射率 (eta は入射側 / 透過側の屈折率)
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let sin2 = eta * eta * (1.0 - cos * cos);
+    if sin2 >= 1.0 {
+        return 1.0;
+    }
+
+    let cos_t = (1.0 - sin2).sqrt();
+    let rs = (eta * cos - cos_t) / (eta * cos + cos_t);
+    let rp = (cos - eta * cos_t) / (cos + eta * cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+// 下の材質の上に重ねるクリアコート (車の塗装やニス)
+#[derive(Clone, Copy)]
+pub struct Coating {
+    pub ior: f64,
+    pub roughness: f64,
+    pub thickness: f64,
+    pub absorption: Spectrum, // コートの中での吸収係数
+}
+
+impl Default for Coating {
+    fn default() -> Self {
+        Coating {
+            ior: 1.5,
+            roughness: 0.0,
+            thickness: 0.0,
+            absorption: BLACK,
+        }
+    }
+}
+
+// 表面下散乱 (肌、蝋、大理石など)。境界を越えた光は内部をランダムウォークする
+#[derive(Clone, Copy)]
+pub struct Subsurface {
+    pub albedo: Spectrum,         // 一回の散乱で残る割合
+    pub mean_free_path: Spectrum, // 散乱か吸収が起きるまでの平均距離
+    pub anisotropy: f64,
+}
+
+impl Subsurface {
+    pub fn monochrome(&self, wavelength: f64) -> Subsurface {
+        Subsurface {
+            albedo: self.albedo.monochrome(wavelength),
+            mean_free_path: self.mean_free_path.monochrome(wavelength),
+            ..*self
+        }
+    }
+
+    pub fn medium(&self) -> Medium {
+        let extinction = self.mean_free_path.map(|d| 1.0 / d);
+        Medium {
+            absorption: extinction * self.albedo.map(|a| 1.0 - a),
+            scattering: extinction * self.albedo,
+            anisotropy: self.anisotropy,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conductor_interpolates_table_per_wavelength() {
+        let gold = Conductor::GOLD;
+
+        let at = gold.monochrome(521.0);
+        assert_eq!((at.eta.r, at.k.g), (0.62, 2.081));
+
+        let between = gold.monochrome(535.0);
+        assert!((between.eta.b - 0.525).abs() < 1e-9);
+        assert!((between.k.r - 2.268).abs() < 1e-9);
+
+        // 表の外側は端の値
+        assert_eq!(gold.monochrome(300.0).eta.g, 1.46);
+        assert_eq!(gold.monochrome(800.0).k.b, 4.542);
+    }
+
+    #[test]
+    fn conductor_without_table_uses_rgb() {
+        let custom = Conductor {
+            table: None,
+            ..Conductor::COPPER
+        };
+        let at = custom.monochrome(600.0);
+        assert_eq!(at.eta.r, Conductor::COPPER.eta.monochrome(600.0).r);
+        assert_eq!(at.k.b, Conductor::COPPER.k.monochrome(600.0).b);
+    }
+
+    #[test]
+    fn gold_reflects_more_red_than_blue() {
+        let red = Conductor::GOLD.monochrome(650.0).fresnel(1.0, 1.0).r;
+        let blue = Conductor::GOLD.monochrome(450.0).fresnel(1.0, 1.0).r;
+        assert!(red > 0.9 && blue < 0.5, "{} {}", red, blue);
+    }
 }
@@ -0,0 +1,241 @@
+use crate::material::Material;
+use crate::random;
+use crate::spectrum::Spectrum;
+use crate::vector::Vector3;
+
+// 均質な関与媒質
+#[derive(Clone, Copy)]
+pub struct Medium {
+    pub absorption: Spectrum, // σa
+    pub scattering: Spectrum, // σs
+    pub anisotropy: f64,      // Henyey-Greenstein の g
+}
+
+pub enum MediumSample {
+    Scattered { distance: f64, weight: Spectrum },
+    Passed { weight: Spectrum },
+}
+
+impl Medium {
+    pub fn monochrome(&self, wavelength: f64) -> Medium {
+        Medium {
+            absorption: self.absorption.monochrome(wavelength),
+            scattering: self.scattering.monochrome(wavelength),
+            ..*self
+        }
+    }
+
+    pub fn extinction(&self) -> Spectrum {
+        self.absorption + self.scattering
+    }
+
+    pub fn transmittance(&self, distance: f64) -> Spectrum {
+        self.extinction().map(|x| (-x * distance).exp())
+    }
+
+    // 波長(チャンネル)を一つ選んで自由行程をサンプリングし、
+    // 全チャンネルの確率密度の平均で割る
+    pub fn sample(&self, max_distance: f64) -> MediumSample {
+        let sigma_t = self.extinction();
+        let channel = sigma_t.channels()[(random(0.0, 3.0) as usize).min(2)];
+
+        if channel > 0.0 {
+            let distance = -(1.0 - random(0.0, 1.0)).ln() / channel;
+
+            if distance < max_distance {
+                let tr = self.transmittance(distance);
+                let pdf = (sigma_t * tr).average();
+                return MediumSample::Scattered {
+                    distance,
+                    weight: (self.scattering * tr).scale(1.0 / pdf),
+                };
+            }
+        }
+
+        let tr = self.transmittance(max_distance);
+        let pdf = tr.average();
+        if pdf <= 0.0 {
+            return MediumSample::Passed {
+                weight: Spectrum::default(),
+            };
+        }
+
+        MediumSample::Passed {
+            weight: tr.scale(1.0 / pdf),
+        }
+    }
+
+    pub fn phase(&self, dir: Vector3, scattered: Vector3) -> f64 {
+        henyey_greenstein(self.anisotropy, dir.dot(&scattered))
+    }
+
+    pub fn sample_phase(&self, dir: Vector3) -> Vector3 {
+        sample_henyey_greenstein(self.anisotropy, dir)
+    }
+}
+
+// 記録できる入れ子の深さ (これより深く入った物体は記録しない)
+const STACK_LIMIT: usize = 8;
+
+// 内部にいる物体とその屈折率
+#[derive(Clone, Copy, Default)]
+pub struct Interior {
+    pub object: usize,
+    pub ior: f64,
+    pub priority: u32,
+}
+
+// パス上で今どの物体の内部にいるか
+#[derive(Clone, Copy, Default)]
+pub struct MediumStack {
+    entries: [Interior; STACK_LIMIT],
+    len: usize,
+}
+
+impl Interior {
+    pub fn new(object: usize, material: &Material) -> Self {
+        Interior {
+            object,
+            ior: material.ior(),
+            priority: material.priority,
+        }
+    }
+}
+
+impl MediumStack {
+    // 優先度が最も高い物体 (同じなら後から入ったもの)
+    pub fn current(&self) -> Option<Interior> {
+        self.entries[..self.len]
+            .iter()
+            .max_by_key(|e| e.priority)
+            .copied()
+    }
+
+    pub fn contains(&self, object: usize) -> bool {
+        self.entries[..self.len].iter().any(|e| e.object == object)
+    }
+
+    pub fn pushed(&self, interior: Interior) -> Self {
+        let mut stack = *self;
+        if stack.len < STACK_LIMIT {
+            stack.entries[stack.len] = interior;
+            stack.len += 1;
+        }
+        stack
+    }
+
+    pub fn removed(&self, object: usize) -> Self {
+        let mut stack = *self;
+        if let Some(i) = self.entries[..self.len]
+            .iter()
+            .rposition(|e| e.object == object)
+        {
+            stack.entries.copy_within(i + 1..self.len, i);
+            stack.len -= 1;
+        }
+        stack
+    }
+}
+
+pub fn henyey_greenstein(g: f64, cos: f64) -> f64 {
+    let denom = 1.0 + g * g - 2.0 * g * cos;
+    (1.0 - g * g) / (4.0 * std::f64::consts::PI * denom * denom.sqrt())
+}
+
+// 位相関数に従って散乱方向を選ぶ (重みは 1)
+pub fn sample_henyey_greenstein(g: f64, dir: Vector3) -> Vector3 {
+    let u = random(0.0, 1.0);
+
+    let cos = if g.abs() < 1e-3 {
+        1.0 - 2.0 * u
+    } else {
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+        (1.0 + g * g - s * s) / (2.0 * g)
+    };
+    let sin = (1.0 - cos * cos).max(0.0).sqrt();
+    let phi = random(0.0, 2.0 * std::f64::consts::PI);
+
+    let (t, b) = dir.orthonormal_basis();
+    t.scale(sin * phi.cos()) + b.scale(sin * phi.sin()) + dir.scale(cos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const UP: Vector3 = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 1.0,
+    };
+
+    // 立体角で積分するので cos で積分して 2π をかける
+    fn integrate(g: f64, lo: f64, hi: f64) -> f64 {
+        let n = 10_000;
+        let step = (hi - lo) / n as f64;
+        (0..n)
+            .map(|i| henyey_greenstein(g, lo + (i as f64 + 0.5) * step) * step)
+            .sum::<f64>()
+            * 2.0
+            * PI
+    }
+
+    #[test]
+    fn henyey_greenstein_integrates_to_one() {
+        for g in [-0.8, -0.3, 0.0, 0.5, 0.9] {
+            let total = integrate(g, -1.0, 1.0);
+            assert!((total - 1.0).abs() < 1e-3, "g = {}: {}", g, total);
+        }
+    }
+
+    #[test]
+    fn sampled_directions_follow_henyey_greenstein() {
+        const BINS: usize = 8;
+        let n = 200_000;
+
+        for g in [-0.6, 0.0, 0.7] {
+            let mut histogram = [0usize; BINS];
+            for _ in 0..n {
+                let d = sample_henyey_greenstein(g, UP);
+                assert!((d.len() - 1.0).abs() < 1e-9);
+                let bin = (((d.z + 1.0) / 2.0 * BINS as f64) as usize).min(BINS - 1);
+                histogram[bin] += 1;
+            }
+
+            for (i, count) in histogram.iter().enumerate() {
+                let lo = -1.0 + 2.0 * i as f64 / BINS as f64;
+                let expected = integrate(g, lo, lo + 2.0 / BINS as f64);
+                let actual = *count as f64 / n as f64;
+                assert!(
+                    (actual - expected).abs() < 0.01,
+                    "g = {}, bin {}: {} vs {}",
+                    g,
+                    i,
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stack_prefers_higher_priority_and_later_entries() {
+        let interior = |object, priority| Interior {
+            object,
+            ior: 1.0 + object as f64,
+            priority,
+        };
+        let stack = MediumStack::default()
+            .pushed(interior(0, 1))
+            .pushed(interior(1, 0))
+            .pushed(interior(2, 1));
+        assert_eq!(stack.current().map(|i| i.object), Some(2));
+        assert!(stack.contains(1));
+
+        let stack = stack.removed(2);
+        assert_eq!(stack.current().map(|i| (i.object, i.ior)), Some((0, 1.0)));
+        let stack = stack.removed(0).removed(1);
+        assert!(stack.current().is_none());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::random;
+use crate::spectrum::{Spectrum, BLACK};
+use crate::vector::Vector3;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+const THETA_H_RES: usize = 90;
+const THETA_D_RES: usize = 90;
+const PHI_D_RES: usize = 180;
+const SAMPLES: usize = THETA_H_RES * THETA_D_RES * PHI_D_RES;
+
+// 各チャンネルの値に掛ける係数 (MERL の配布コードに合わせる)
+const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+
+// 重点サンプリング用の表の分割数
+const THETA_O_BINS: usize = 16;
+const THETA_I_BINS: usize = 32;
+const PHI_BINS: usize = 64;
+
+// 表にない方向も拾えるように余弦分布と混ぜる割合
+const COSINE_FRACTION: f64 = 0.1;
+
+// MERL 形式の等方的な測定 BRDF
+pub struct MeasuredBrdf {
+    data: Vec<f64>,
+    cdfs: Vec<Vec<f64>>, // 出射方向の天頂角ごとの、入射方向のセルの累積分布
+}
+
+fn rotate_z(v: Vector3, angle: f64) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    Vector3 {
+        x: v.x * c - v.y * s,
+        y: v.x * s + v.y * c,
+        z: v.z,
+    }
+}
+
+fn rotate_y(v: Vector3, angle: f64) -> Vector3 {
+    let (s, c) = angle.sin_cos();
+    Vector3 {
+        x: v.x * c + v.z * s,
+        y: v.y,
+        z: -v.x * s + v.z * c,
+    }
+}
+
+fn spherical(theta: f64, phi: f64) -> Vector3 {
+    Vector3 {
+        x: theta.sin() * phi.cos(),
+        y: theta.sin() * phi.sin(),
+        z: theta.cos(),
+    }
+}
+
+impl MeasuredBrdf {
+    // 先頭に 3 つの i32 (解像度)、続いて R, G, B の順に f64 が並ぶ (リトルエンディアン)
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut buf = vec![];
+        File::open(path)?.read_to_end(&mut buf)?;
+        Self::parse(&buf)
+    }
+
+    fn parse(buf: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        if buf.len() < 12 {
+            return Err(invalid("truncated header"));
+        }
+
+        // 解像度は θh, θd, φd の順に 90, 90, 180 でなければならない
+        let dims: Vec<i32> = buf[..12]
+            .chunks_exact(4)
+            .map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .collect();
+        if dims != [THETA_H_RES as i32, THETA_D_RES as i32, PHI_D_RES as i32] {
+            return Err(invalid("unexpected resolution"));
+        }
+
+        let data: Vec<f64> = buf[12..]
+            .chunks_exact(8)
+            .map(|x| f64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]]))
+            .collect();
+        if data.len() != SAMPLES * 3 {
+            return Err(invalid("unexpected data length"));
+        }
+
+        let mut brdf = Self { data, cdfs: vec![] };
+        brdf.cdfs = (0..THETA_O_BINS).map(|i| brdf.build_cdf(i)).collect();
+        Ok(brdf)
+    }
+
+    // 出射方向を φ = 0 に置いた局所座標での値
+    fn lookup(&self, wo: Vector3, wi: Vector3) -> Spectrum {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return BLACK;
+        }
+
+        // ハーフベクトルと、それから見た入射方向 (差分ベクトル) の角度で引く
+        let h = (wo + wi).normalize();
+        let theta_h = h.z.clamp(-1.0, 1.0).acos();
+        let phi_h = h.y.atan2(h.x);
+        let d = rotate_y(rotate_z(wi, -phi_h), -theta_h);
+        let theta_d = d.z.clamp(-1.0, 1.0).acos();
+        let mut phi_d = d.y.atan2(d.x);
+        if phi_d < 0.0 {
+            phi_d += PI;
+        }
+
+        let index = |x: f64, n: usize| (x as usize).min(n - 1);
+        let ih = index(
+            (theta_h / FRAC_PI_2).sqrt() * THETA_H_RES as f64,
+            THETA_H_RES,
+        );
+        let id = index(theta_d / FRAC_PI_2 * THETA_D_RES as f64, THETA_D_RES);
+        let ip = index(phi_d / PI * PHI_D_RES as f64, PHI_D_RES);
+        let i = ip + (id + ih * THETA_D_RES) * PHI_D_RES;
+
+        let channel = |c: usize| (self.data[i + c * SAMPLES] * SCALE[c]).max(0.0);
+        Spectrum {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
+        }
+    }
+
+    fn build_cdf(&self, theta_o_bin: usize) -> Vec<f64> {
+        let theta_o = (theta_o_bin as f64 + 0.5) / THETA_O_BINS as f64 * FRAC_PI_2;
+        let wo = spherical(theta_o, 0.0);
+
+        let mut sum = 0.0;
+        let mut cdf = Vec::with_capacity(THETA_I_BINS * PHI_BINS);
+        for i in 0..THETA_I_BINS {
+            let theta = (i as f64 + 0.5) / THETA_I_BINS as f64 * FRAC_PI_2;
+            for j in 0..PHI_BINS {
+                let phi = (j as f64 + 0.5) / PHI_BINS as f64 * 2.0 * PI;
+                let f = self.lookup(wo, spherical(theta, phi));
+
+                // f cosθ を (θ, φ) の面積あたりに直す
+                sum += f.average() * theta.cos() * theta.sin();
+                cdf.push(sum);
+            }
+        }
+
+        cdf
+    }
+
+    // 出射方向の方位角が 0 になる接空間
+    fn frame(normal: Vector3, wo: Vector3) -> (Vector3, Vector3) {
+        let t = wo - normal.scale(normal.dot(&wo));
+        if t.len() < 1e-8 {
+            return normal.orthonormal_basis();
+        }
+
+        let t = t.normalize();
+        (t, normal.cross(t))
+    }
+
+    fn cdf_for(&self, cos_o: f64) -> &Vec<f64> {
+        let theta_o = cos_o.clamp(-1.0, 1.0).acos();
+        let bin = ((theta_o / FRAC_PI_2 * THETA_O_BINS as f64) as usize).min(THETA_O_BINS - 1);
+        &self.cdfs[bin]
+    }
+
+    pub fn eval(&self, normal: Vector3, wo: Vector3, wi: Vector3) -> Spectrum {
+        let (t, b) = Self::frame(normal, wo);
+        let local = |v: Vector3| Vector3 {
+            x: v.dot(&t),
+            y: v.dot(&b),
+            z: v.dot(&normal),
+        };
+        self.lookup(local(wo), local(wi))
+    }
+
+    pub fn pdf(&self, normal: Vector3, wo: Vector3, wi: Vector3) -> f64 {
+        let cos_i = normal.dot(&wi);
+        if cos_i <= 0.0 {
+            return 0.0;
+        }
+        let cosine = cos_i / PI;
+
+        let cdf = self.cdf_for(normal.dot(&wo));
+        let total = cdf[cdf.len() - 1];
+        if total <= 0.0 {
+            return cosine;
+        }
+
+        let (t, b) = Self::frame(normal, wo);
+        let theta = cos_i.min(1.0).acos();
+        let phi = wi.dot(&b).atan2(wi.dot(&t)).rem_euclid(2.0 * PI);
+
+        let i = ((theta / FRAC_PI_2 * THETA_I_BINS as f64) as usize).min(THETA_I_BINS - 1);
+        let j = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+        let cell = i * PHI_BINS + j;
+        let p = (cdf[cell] - if cell > 0 { cdf[cell - 1] } else { 0.0 }) / total;
+
+        // セルの (θ, φ) の面積で割り、sinθ で立体角あたりに直す
+        let area = FRAC_PI_2 / THETA_I_BINS as f64 * 2.0 * PI / PHI_BINS as f64;
+        let table = p / (area * theta.sin().max(1e-6));
+
+        (1.0 - COSINE_FRACTION) * table + COSINE_FRACTION * cosine
+    }
+
+    // 入射方向をサンプリングし、f * cosθ / pdf とともに返す
+    pub fn sample(&self, normal: Vector3, wo: Vector3) -> Option<(Vector3, Spectrum)> {
+        if normal.dot(&wo) <= 0.0 {
+            return None;
+        }
+
+        let cdf = self.cdf_for(normal.dot(&wo));
+        let total = cdf[cdf.len() - 1];
+        let (t, b) = Self::frame(normal, wo);
+
+        let wi = if total <= 0.0 || random(0.0, 1.0) < COSINE_FRACTION {
+            let r = random(0.0, 1.0).sqrt();
+            let phi = random(0.0, 2.0 * PI);
+            t.scale(r * phi.cos()) + b.scale(r * phi.sin()) + normal.scale((1.0 - r * r).sqrt())
+        } else {
+            let u = random(0.0, total);
+            let cell = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+            let (i, j) = (cell / PHI_BINS, cell % PHI_BINS);
+
+            let theta = (i as f64 + random(0.0, 1.0)) / THETA_I_BINS as f64 * FRAC_PI_2;
+            let phi = (j as f64 + random(0.0, 1.0)) / PHI_BINS as f64 * 2.0 * PI;
+            let v = spherical(theta, phi);
+            t.scale(v.x) + b.scale(v.y) + normal.scale(v.z)
+        };
+
+        let pdf = self.pdf(normal, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let f = self.eval(normal, wo, wi);
+        Some((wi, f.scale(normal.dot(&wi) / pdf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各チャンネルの値を添字から決めたファイルの中身
+    fn file(dims: [i32; 3], value: impl Fn(usize) -> f64) -> Vec<u8> {
+        let mut bytes = vec![];
+        for d in dims.iter() {
+            bytes.extend_from_slice(&d.to_le_bytes());
+        }
+        for i in 0..SAMPLES * 3 {
+            bytes.extend_from_slice(&value(i).to_le_bytes());
+        }
+        bytes
+    }
+
+    fn value(i: usize) -> f64 {
+        (i % 997) as f64 + 1.0
+    }
+
+    #[test]
+    fn parse_keeps_values_in_order() {
+        let brdf = MeasuredBrdf::parse(&file([90, 90, 180], value)).unwrap();
+        assert_eq!(brdf.data.len(), SAMPLES * 3);
+        assert!(brdf.data.iter().enumerate().all(|(i, &x)| x == value(i)));
+
+        // 真上から入って真上に出る光は θh = θd = φd = 0 の値
+        let up = spherical(0.0, 0.0);
+        assert_eq!(brdf.lookup(up, up).g, value(SAMPLES) * SCALE[1]);
+    }
+
+    #[test]
+    fn parse_rejects_other_resolutions() {
+        for dims in [
+            [90, 180, 90],
+            [-90, -90, 180],
+            [0, 90, 180],
+            [i32::MAX, 2, 2],
+        ]
+        .iter()
+        {
+            let mut bytes = file([90, 90, 180], value);
+            for (i, d) in dims.iter().enumerate() {
+                bytes[i * 4..i * 4 + 4].copy_from_slice(&d.to_le_bytes());
+            }
+            let err = MeasuredBrdf::parse(&bytes).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut short = file([90, 90, 180], value);
+        short.truncate(short.len() - 8);
+        assert!(MeasuredBrdf::parse(&short).is_err());
+    }
+
+    #[test]
+    fn lookup_is_reciprocal() {
+        let brdf = MeasuredBrdf::parse(&file([90, 90, 180], value)).unwrap();
+        for _ in 0..1000 {
+            let a = spherical(random(0.0, 1.5), random(0.0, 2.0 * PI));
+            let b = spherical(random(0.0, 1.5), random(0.0, 2.0 * PI));
+            assert_eq!(brdf.lookup(a, b).channels(), brdf.lookup(b, a).channels());
+        }
+    }
+}
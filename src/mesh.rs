@@ -0,0 +1,321 @@
+use crate::intersect::{Intersectable, Intersection, SurfaceDifferentials};
+use crate::light_tree::LightBounds;
+use crate::material::Material;
+use crate::random;
+use crate::ray::Ray;
+use crate::vector::Vector3;
+use once_cell::sync::OnceCell;
+
+// 三角形メッシュ (表は反時計回り)
+pub struct Mesh {
+    pub positions: Vec<Vector3>,
+    pub uvs: Vec<(f64, f64)>, // 頂点ごと (空なら全て (0, 0))
+    pub triangles: Vec<[usize; 3]>,
+    pub material: Material,
+    area_cdf: OnceCell<Vec<f64>>, // 三角形の面積の累積和 (最初に光源として使うときに作る)
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        triangles: Vec<[usize; 3]>,
+        material: Material,
+    ) -> Self {
+        Self {
+            positions,
+            uvs,
+            triangles,
+            material,
+            area_cdf: OnceCell::new(),
+        }
+    }
+
+    // corner を左下として u, v 方向に張った四角形
+    pub fn quad(corner: Vector3, u: Vector3, v: Vector3, material: Material) -> Self {
+        Self::new(
+            vec![corner, corner + u, corner + u + v, corner + v],
+            vec![(0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material,
+        )
+    }
+
+    // Möller-Trumbore 法。距離と重心座標を返す
+    fn intersect_triangle(&self, ray: &Ray, triangle: &[usize; 3]) -> Option<(f64, f64, f64)> {
+        let p0 = self.positions[triangle[0]];
+        let e1 = self.positions[triangle[1]] - p0;
+        let e2 = self.positions[triangle[2]] - p0;
+
+        let p = ray.dir.cross(e2);
+        let det = e1.dot(&p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let s = ray.origin - p0;
+        let b1 = s.dot(&p) / det;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+
+        let q = s.cross(e1);
+        let b2 = ray.dir.dot(&q) / det;
+        if b2 < 0.0 || 1.0 < b1 + b2 {
+            return None;
+        }
+
+        let t = e2.dot(&q) / det;
+        if t <= 0.0 {
+            return None;
+        }
+
+        Some((t, b1, b2))
+    }
+
+    fn area(&self, triangle: &[usize; 3]) -> f64 {
+        let p0 = self.positions[triangle[0]];
+        let e1 = self.positions[triangle[1]] - p0;
+        let e2 = self.positions[triangle[2]] - p0;
+        0.5 * e1.cross(e2).len()
+    }
+
+    fn area_cdf(&self) -> &[f64] {
+        self.area_cdf.get_or_init(|| {
+            self.triangles
+                .iter()
+                .scan(0.0, |sum, t| {
+                    *sum += self.area(t);
+                    Some(*sum)
+                })
+                .collect()
+        })
+    }
+
+    fn total_area(&self) -> f64 {
+        self.area_cdf().last().copied().unwrap_or(0.0)
+    }
+
+    // 重心座標 (b1, b2) の点での交点の情報 (distance は 0 のまま)
+    fn surface(&self, triangle: &[usize; 3], b1: f64, b2: f64) -> Intersection<'_> {
+        let [i0, i1, i2] = *triangle;
+        let p0 = self.positions[i0];
+        let normal = (self.positions[i1] - p0)
+            .cross(self.positions[i2] - p0)
+            .normalize();
+
+        let b0 = 1.0 - b1 - b2;
+        let uvs = if self.uvs.is_empty() {
+            [(0.0, 0.0); 3]
+        } else {
+            [self.uvs[i0], self.uvs[i1], self.uvs[i2]]
+        };
+        let uv = (
+            b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0,
+            b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1,
+        );
+
+        // UV の変化から接ベクトルを求める
+        let (du02, dv02) = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+        let (du12, dv12) = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+        let dp02 = p0 - self.positions[i2];
+        let dp12 = self.positions[i1] - self.positions[i2];
+        let det = du02 * dv12 - dv02 * du12;
+
+        let (dpdu, dpdv) = if det.abs() < 1e-12 {
+            normal.orthonormal_basis()
+        } else {
+            (
+                (dp02.scale(dv12) - dp12.scale(dv02)).scale(1.0 / det),
+                (dp12.scale(du02) - dp02.scale(du12)).scale(1.0 / det),
+            )
+        };
+
+        Intersection {
+            distance: 0.0,
+            point: p0.scale(b0) + self.positions[i1].scale(b1) + self.positions[i2].scale(b2),
+            normal,
+            shading_normal: normal,
+            tangent: Vector3::default(),
+            uv,
+            dpdu,
+            dpdv,
+            dndu: Vector3::default(),
+            dndv: Vector3::default(),
+            material: &self.material,
+            differentials: SurfaceDifferentials::default(),
+        }
+    }
+}
+
+impl Intersectable for Mesh {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (triangle, (t, b1, b2)) = self
+            .triangles
+            .iter()
+            .flat_map(|tri| self.intersect_triangle(ray, tri).map(|x| (tri, x)))
+            .min_by(|a, b| (a.1).0.partial_cmp(&(b.1).0).unwrap())?;
+
+        Some(Intersection {
+            distance: t,
+            point: ray.origin + ray.dir.scale(t),
+            ..self.surface(triangle, b1, b2)
+        })
+    }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        if !self.material.is_emissive() || self.triangles.is_empty() {
+            return None;
+        }
+
+        let first = self.positions[self.triangles[0][0]];
+        let points = self.triangles.iter().flatten().map(|&i| self.positions[i]);
+        let (min, max) = points.fold((first, first), |(lo, hi), p| {
+            (
+                Vector3 {
+                    x: lo.x.min(p.x),
+                    y: lo.y.min(p.y),
+                    z: lo.z.min(p.z),
+                },
+                Vector3 {
+                    x: hi.x.max(p.x),
+                    y: hi.y.max(p.y),
+                    z: hi.z.max(p.z),
+                },
+            )
+        });
+
+        Some(LightBounds {
+            min,
+            max,
+            power: self.material.emissive.average() * self.total_area(),
+        })
+    }
+
+    fn sample_light(&self, point: Vector3) -> Option<(Intersection<'_>, f64)> {
+        let cdf = self.area_cdf();
+        let total = self.total_area();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // 面積に比例して三角形を選ぶ
+        let u = random(0.0, total);
+        let index = cdf.partition_point(|&c| c <= u).min(cdf.len() - 1);
+
+        // 三角形の中で一様になる重心座標
+        let r = random(0.0, 1.0).sqrt();
+        let v = random(0.0, 1.0);
+        let surface = self.surface(&self.triangles[index], r * (1.0 - v), r * v);
+
+        // 交差判定と同じく、不透明度に応じて素通りする点は光らない
+        if !self.material.stops_at(&surface.texture_point()) {
+            return None;
+        }
+
+        let pdf = self.light_pdf(point, &surface);
+        (pdf > 0.0).then_some((surface, pdf))
+    }
+
+    // 面積あたりの確率密度 1 / (全体の面積) を立体角あたりに直す
+    fn light_pdf(&self, point: Vector3, x: &Intersection) -> f64 {
+        let d = x.point - point;
+        let cos = -d.normalize().dot(&x.normal);
+        if cos <= 0.0 {
+            return 0.0;
+        }
+
+        d.dot(&d) / (cos * self.total_area())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f64, y: f64) -> Vector3 {
+        Vector3 { x, y, z: 0.0 }
+    }
+
+    // 面積 0.5 と 1.5 の三角形 (表は +z)
+    fn mesh() -> Mesh {
+        Mesh::new(
+            vec![
+                point(0.0, 0.0),
+                point(1.0, 0.0),
+                point(0.0, 1.0),
+                point(2.0, 0.0),
+                point(5.0, 0.0),
+                point(2.0, 1.0),
+            ],
+            vec![],
+            vec![[0, 1, 2], [3, 4, 5]],
+            Material::default(),
+        )
+    }
+
+    #[test]
+    fn sample_light_picks_triangles_by_area() {
+        let mesh = mesh();
+        let origin = Vector3 {
+            x: 1.0,
+            y: 0.5,
+            z: 1.0,
+        };
+
+        let n = 100_000;
+        let mut large = 0;
+        for _ in 0..n {
+            let (surface, pdf) = mesh.sample_light(origin).unwrap();
+            assert!((pdf - mesh.light_pdf(origin, &surface)).abs() < 1e-9);
+            if surface.point.x >= 2.0 {
+                large += 1;
+            }
+        }
+
+        let fraction = large as f64 / n as f64;
+        assert!((fraction - 0.75).abs() < 0.01, "{}", fraction);
+    }
+
+    #[test]
+    fn sample_light_skips_transparent_points() {
+        let mesh = Mesh {
+            material: Material {
+                opacity: 0.25,
+                ..Material::default()
+            },
+            ..mesh()
+        };
+        let origin = Vector3 {
+            x: 1.0,
+            y: 0.5,
+            z: 1.0,
+        };
+
+        let n = 100_000;
+        let sampled = (0..n)
+            .filter(|_| mesh.sample_light(origin).is_some())
+            .count();
+        let fraction = sampled as f64 / n as f64;
+        assert!((fraction - 0.25).abs() < 0.01, "{}", fraction);
+    }
+
+    #[test]
+    fn light_pdf_uses_total_area() {
+        let mesh = mesh();
+        let surface = mesh.surface(&mesh.triangles[0], 0.25, 0.25);
+        let above = surface.point
+            + Vector3 {
+                x: 0.0,
+                y: 0.0,
+                z: 2.0,
+            };
+
+        // 真上から見ると cos = 1 なので距離の二乗 / 全体の面積
+        assert!((mesh.light_pdf(above, &surface) - 4.0 / 2.0).abs() < 1e-9);
+    }
+}
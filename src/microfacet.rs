@@ -0,0 +1,111 @@
+use crate::random;
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+// GGX (Trowbridge-Reitz) マイクロファセット分布
+pub struct Ggx {
+    pub alpha_x: f64, // tangent 方向の粗さ
+    pub alpha_y: f64,
+    pub tangent: Vector3, // 等方的なら使わない
+}
+
+impl Ggx {
+    // 見た目の粗さから (alpha = roughness^2)
+    pub fn from_roughness(roughness: f64) -> Self {
+        let alpha = (roughness * roughness).max(1e-4);
+        Self {
+            alpha_x: alpha,
+            alpha_y: alpha,
+            tangent: Vector3::default(),
+        }
+    }
+
+    // anisotropy が 1 に近いほど tangent 方向に伸びたハイライトになる
+    pub fn anisotropic(roughness: f64, anisotropy: f64, tangent: Vector3) -> Self {
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        let alpha = roughness * roughness;
+        Self {
+            alpha_x: (alpha / aspect).max(1e-4),
+            alpha_y: (alpha * aspect).max(1e-4),
+            tangent,
+        }
+    }
+
+    // 法線に直交するように tangent を直した接空間
+    fn frame(&self, normal: Vector3) -> (Vector3, Vector3) {
+        let t = self.tangent - normal.scale(normal.dot(&self.tangent));
+        if t.len() < 1e-8 {
+            return normal.orthonormal_basis();
+        }
+
+        let t = t.normalize();
+        (t, normal.cross(t))
+    }
+
+    fn local(&self, normal: Vector3, v: Vector3) -> (f64, f64, f64) {
+        let (t, b) = self.frame(normal);
+        (v.dot(&t), v.dot(&b), v.dot(&normal))
+    }
+
+    pub fn d(&self, normal: Vector3, h: Vector3) -> f64 {
+        let (x, y, z) = self.local(normal, h);
+        if z <= 0.0 {
+            return 0.0;
+        }
+
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        let t = (x / ax) * (x / ax) + (y / ay) * (y / ay) + z * z;
+        1.0 / (PI * ax * ay * t * t)
+    }
+
+    pub fn g1(&self, normal: Vector3, v: Vector3) -> f64 {
+        let (x, y, z) = self.local(normal, v);
+        let z = z.abs();
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        2.0 * z / (z + (ax * ax * x * x + ay * ay * y * y + z * z).sqrt())
+    }
+
+    pub fn g(&self, normal: Vector3, wo: Vector3, wi: Vector3) -> f64 {
+        self.g1(normal, wo) * self.g1(normal, wi)
+    }
+
+    // D(h) cosθh に従ってマイクロファセットの法線を選ぶ (α = 1 の分布の傾きを引き伸ばす)
+    pub fn sample_h(&self, normal: Vector3) -> Vector3 {
+        let u = random(0.0, 1.0);
+        let slope = (u / (1.0 - u)).sqrt();
+        let phi = random(0.0, 2.0 * PI);
+
+        let (t, b) = self.frame(normal);
+        (t.scale(-self.alpha_x * slope * phi.cos())
+            + b.scale(-self.alpha_y * slope * phi.sin())
+            + normal)
+            .normalize()
+    }
+
+    // 反射方向の確率密度 (立体角)
+    pub fn pdf_reflection(&self, normal: Vector3, wo: Vector3, wi: Vector3) -> f64 {
+        let h = (wo + wi).normalize();
+        let cos = wo.dot(&h).abs();
+        if cos <= 0.0 {
+            return 0.0;
+        }
+
+        self.d(normal, h) * normal.dot(&h).abs() / (4.0 * cos)
+    }
+
+    // 反射方向をサンプリングし、f * cosθ / pdf (フレネル項を除く) とともに返す
+    pub fn sample_reflection(&self, ray_dir: Vector3, normal: Vector3) -> Option<(Vector3, f64)> {
+        let wo = -ray_dir;
+        let h = self.sample_h(normal);
+        let wi = ray_dir.reflect(&h);
+
+        let cos_o = normal.dot(&wo);
+        let cos_i = normal.dot(&wi);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return None;
+        }
+
+        let weight = self.g(normal, wo, wi) * wo.dot(&h) / (cos_o * normal.dot(&h));
+        Some((wi, weight))
+    }
+}
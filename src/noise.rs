@@ -0,0 +1,261 @@
+use crate::spectrum::Spectrum;
+use crate::texture::{Texture, TexturePoint};
+use crate::vector::Vector3;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::sync::Arc;
+
+// Perlin の改良版ノイズ (値はおよそ -1 から 1)
+pub struct Perlin {
+    perm: Vec<usize>,
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// ハッシュの下位 4 ビットで立方体の辺の中点方向の勾配を選ぶ
+fn grad(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut perm: Vec<usize> = (0..256).collect();
+        perm.shuffle(&mut SmallRng::seed_from_u64(seed));
+
+        // 折り返しの計算を省くため 2 周分持っておく
+        let perm = perm.iter().chain(perm.iter()).copied().collect();
+        Self { perm }
+    }
+
+    fn hash(&self, x: i64, y: i64, z: i64) -> usize {
+        let p = &self.perm;
+        p[p[p[(x & 255) as usize] + (y & 255) as usize] + (z & 255) as usize]
+    }
+
+    pub fn noise(&self, p: Vector3) -> f64 {
+        let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+        let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let g = |dx: i64, dy: i64, dz: i64| {
+            grad(
+                self.hash(xi + dx, yi + dy, zi + dz),
+                x - dx as f64,
+                y - dy as f64,
+                z - dz as f64,
+            )
+        };
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, g(0, 0, 0), g(1, 0, 0)),
+                lerp(u, g(0, 1, 0), g(1, 1, 0)),
+            ),
+            lerp(
+                v,
+                lerp(u, g(0, 0, 1), g(1, 0, 1)),
+                lerp(u, g(0, 1, 1), g(1, 1, 1)),
+            ),
+        )
+    }
+
+    // オクターブを重ねる。footprint はピクセルの広がりで、それより細かいオクターブは省く
+    fn octaves<F: Fn(f64) -> f64>(&self, p: Vector3, octaves: u32, footprint: f64, f: F) -> f64 {
+        let limit = if footprint > 0.0 {
+            (-1.0 - footprint.log2()).clamp(0.0, octaves as f64)
+        } else {
+            octaves as f64
+        };
+
+        let mut sum = 0.0;
+        let mut frequency = 1.0;
+        let mut amplitude = 1.0;
+        for i in 0..limit.ceil() as u32 {
+            // 最後のオクターブは途中で切れる分だけ弱める
+            let fraction = (limit - i as f64).min(1.0);
+            sum += f(self.noise(p.scale(frequency))) * amplitude * fraction;
+            frequency *= 1.99;
+            amplitude *= 0.5;
+        }
+
+        sum
+    }
+
+    pub fn fbm(&self, p: Vector3, octaves: u32, footprint: f64) -> f64 {
+        self.octaves(p, octaves, footprint, |n| n)
+    }
+
+    pub fn turbulence(&self, p: Vector3, octaves: u32, footprint: f64) -> f64 {
+        self.octaves(p, octaves, footprint, f64::abs)
+    }
+}
+
+// 格子ごとに一つ特徴点を置いたセルラーノイズ
+pub struct Worley {
+    seed: u64,
+}
+
+// SplitMix64
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl Worley {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    fn feature_point(&self, x: i64, y: i64, z: i64) -> Vector3 {
+        let h = mix(self.seed ^ mix(x as u64 ^ mix(y as u64 ^ mix(z as u64))));
+        let unit = |shift: u32| ((h >> shift) & 0x1f_ffff) as f64 / 0x20_0000 as f64;
+
+        Vector3 {
+            x: x as f64 + unit(0),
+            y: y as f64 + unit(21),
+            z: z as f64 + unit(42),
+        }
+    }
+
+    // 最も近い特徴点と二番目に近い特徴点までの距離
+    pub fn distances(&self, p: Vector3) -> (f64, f64) {
+        let (xi, yi, zi) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+
+        for dz in -1..=1 {
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    let d = (self.feature_point(xi + dx, yi + dy, zi + dz) - p).len();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+
+        (f1, f2)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Pattern {
+    Fbm {
+        octaves: u32,
+    },
+    Turbulence {
+        octaves: u32,
+    },
+    // x 方向の縞を乱流で歪ませる
+    Marble {
+        octaves: u32,
+        stripes: f64,
+        distortion: f64,
+    },
+    // y 軸まわりの年輪
+    Wood {
+        rings: f64,
+        distortion: f64,
+    },
+    // 最も近い特徴点までの距離
+    Cellular,
+    // 二番目と一番目の距離の差 (セルの境界が暗くなる)
+    CellEdges,
+}
+
+// 交点の位置から求めた 0 から 1 の値で low と high を混ぜる
+pub struct NoiseTexture {
+    pub pattern: Pattern,
+    pub scale: f64, // 模様の大きさ
+    pub low: Arc<dyn Texture>,
+    pub high: Arc<dyn Texture>,
+    perlin: Perlin,
+    worley: Worley,
+}
+
+impl NoiseTexture {
+    pub fn new(
+        pattern: Pattern,
+        seed: u64,
+        scale: f64,
+        low: Arc<dyn Texture>,
+        high: Arc<dyn Texture>,
+    ) -> Self {
+        Self {
+            pattern,
+            scale,
+            low,
+            high,
+            perlin: Perlin::new(seed),
+            worley: Worley::new(seed),
+        }
+    }
+
+    // 模様の 0 から 1 の値
+    fn amount(&self, p: &TexturePoint) -> f64 {
+        let point = p.point.scale(1.0 / self.scale);
+        let footprint = p.dpdx.len().max(p.dpdy.len()) / self.scale;
+
+        let t = match self.pattern {
+            Pattern::Fbm { octaves } => 0.5 + 0.5 * self.perlin.fbm(point, octaves, footprint),
+            Pattern::Turbulence { octaves } => self.perlin.turbulence(point, octaves, footprint),
+            Pattern::Marble {
+                octaves,
+                stripes,
+                distortion,
+            } => {
+                let turbulence = self.perlin.turbulence(point, octaves, footprint);
+                0.5 + 0.5 * (stripes * point.x + distortion * turbulence).sin()
+            }
+            Pattern::Wood { rings, distortion } => {
+                let r = point.x.hypot(point.z) * rings
+                    + distortion * self.perlin.noise(point.scale(2.0));
+                r - r.floor()
+            }
+            Pattern::Cellular => self.worley.distances(point).0,
+            Pattern::CellEdges => {
+                let (f1, f2) = self.worley.distances(point);
+                f2 - f1
+            }
+        };
+
+        t.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        let t = self.amount(p);
+        self.low.evaluate(p).scale(1.0 - t) + self.high.evaluate(p).scale(t)
+    }
+
+    // バンプマップなど色以外に使うときは模様の値をそのまま使う
+    fn value(&self, p: &TexturePoint) -> f64 {
+        self.amount(p)
+    }
+}
@@ -1,4 +1,4 @@
-use crate::intersect::{Intersectable, Intersection};
+use crate::intersect::{Intersectable, Intersection, SurfaceDifferentials};
 use crate::material::Material;
 use crate::ray::Ray;
 use crate::vector::Vector3;
@@ -22,19 +22,34 @@ impl Plane {
 }
 
 impl Intersectable for Plane {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let v = self.normal.dot(&ray.dir);
         let t = -(self.normal.dot(&ray.origin) + self.distance) / v;
 
         if 0.0 < t {
+            let point = ray.origin + ray.dir.scale(t);
+            let (tangent, bitangent) = self.normal.orthonormal_basis();
+
             return Some(Intersection {
                 distance: t,
-                point: ray.origin + ray.dir.scale(t),
+                point,
                 normal: self.normal,
-                material: self.material.clone(),
+                shading_normal: self.normal,
+                tangent: Vector3::default(),
+                uv: (point.dot(&tangent), point.dot(&bitangent)),
+                dpdu: tangent,
+                dpdv: bitangent,
+                dndu: Vector3::default(),
+                dndv: Vector3::default(),
+                material: &self.material,
+                differentials: SurfaceDifferentials::default(),
             });
         }
 
         None
     }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
 }
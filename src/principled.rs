@@ -0,0 +1,223 @@
+use crate::microfacet::Ggx;
+use crate::random;
+use crate::spectrum::{Spectrum, BLACK};
+use crate::vector::Vector3;
+use std::f64::consts::PI;
+
+// Disney の principled BSDF のパラメータ
+// (ベースカラーは Material::diffuse、粗さは Material::roughness を使う)
+#[derive(Clone, Copy)]
+pub struct Principled {
+    pub metallic: f64,
+    pub specular: f64, // 誘電体としての鏡面反射の強さ (0.5 で屈折率 1.5 相当)
+    pub specular_tint: f64,
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    pub transmission: f64,
+}
+
+impl Default for Principled {
+    fn default() -> Self {
+        Principled {
+            metallic: 0.0,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+        }
+    }
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos).clamp(0.0, 1.0).powi(5)
+}
+
+fn mix(a: Spectrum, b: Spectrum, t: f64) -> Spectrum {
+    a.scale(1.0 - t) + b.scale(t)
+}
+
+const WHITE: Spectrum = Spectrum {
+    r: 1.0,
+    g: 1.0,
+    b: 1.0,
+};
+
+// クリアコート用の GTR1 分布
+fn gtr1(cos: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos * cos))
+}
+
+fn sample_gtr1(normal: Vector3, alpha: f64) -> Vector3 {
+    let a2 = alpha * alpha;
+    let cos2 = (1.0 - a2.powf(1.0 - random(0.0, 1.0))) / (1.0 - a2);
+    let cos = cos2.max(0.0).sqrt();
+    let sin = (1.0 - cos2).max(0.0).sqrt();
+    let phi = random(0.0, 2.0 * PI);
+
+    let (t, b) = normal.orthonormal_basis();
+    t.scale(sin * phi.cos()) + b.scale(sin * phi.sin()) + normal.scale(cos)
+}
+
+fn sample_cosine(normal: Vector3) -> Vector3 {
+    let r = random(0.0, 1.0).sqrt();
+    let phi = random(0.0, 2.0 * PI);
+
+    let (t, b) = normal.orthonormal_basis();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    t.scale(r * phi.cos()) + b.scale(r * phi.sin()) + normal.scale(z)
+}
+
+impl Principled {
+    // 屈折に回す確率 (金属は透過しない)
+    pub fn transmission_weight(&self) -> f64 {
+        self.transmission * (1.0 - self.metallic)
+    }
+
+    // 垂直入射の反射率 0.08 * specular から求めた屈折率 (透過に使う)
+    pub fn ior(&self) -> f64 {
+        2.0 / (1.0 - (0.08 * self.specular).sqrt()) - 1.0
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        0.1 * (1.0 - self.clearcoat_gloss) + 0.001 * self.clearcoat_gloss
+    }
+
+    // 拡散、鏡面、クリアコートの各ローブを選ぶ確率
+    fn lobe_weights(&self) -> [f64; 3] {
+        let diffuse = 1.0 - self.metallic;
+        let clearcoat = 0.25 * self.clearcoat;
+        let sum = diffuse + 1.0 + clearcoat;
+        [diffuse / sum, 1.0 / sum, clearcoat / sum]
+    }
+
+    // 反射側の BSDF の値 (透過は屈折に回した残りの分で、ここには含めない)
+    pub fn eval(
+        &self,
+        base: Spectrum,
+        roughness: f64,
+        normal: Vector3,
+        wo: Vector3,
+        wi: Vector3,
+    ) -> Spectrum {
+        let cos_o = normal.dot(&wo);
+        let cos_i = normal.dot(&wi);
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return BLACK;
+        }
+
+        let h = (wo + wi).normalize();
+        let cos_d = wi.dot(&h);
+
+        let luminance = 0.3 * base.r + 0.6 * base.g + 0.1 * base.b;
+        let tint = if luminance > 0.0 {
+            base.scale(1.0 / luminance)
+        } else {
+            WHITE
+        };
+
+        // 拡散 (粗い面ほど浅い角度で明るくなる) とシーン
+        let fd90 = 0.5 + 2.0 * roughness * cos_d * cos_d;
+        let fd = (1.0 + (fd90 - 1.0) * schlick_weight(cos_i))
+            * (1.0 + (fd90 - 1.0) * schlick_weight(cos_o));
+        let sheen = mix(WHITE, tint, self.sheen_tint).scale(self.sheen * schlick_weight(cos_d));
+        let diffuse = (base.scale(fd / PI) + sheen).scale(1.0 - self.metallic);
+
+        // 鏡面 (金属ではベースカラーがそのまま反射率になる)
+        let ggx = Ggx::from_roughness(roughness);
+        let f0 = mix(
+            mix(WHITE, tint, self.specular_tint).scale(0.08 * self.specular),
+            base,
+            self.metallic,
+        );
+        let f = mix(f0, WHITE, schlick_weight(cos_d));
+        let specular = f.scale(ggx.d(normal, h) * ggx.g(normal, wo, wi) / (4.0 * cos_o * cos_i));
+
+        // クリアコート (屈折率 1.5 の無色の層)
+        let clearcoat = if self.clearcoat > 0.0 {
+            let g = Ggx::from_roughness(0.5);
+            let f = 0.04 + 0.96 * schlick_weight(cos_d);
+            0.25 * self.clearcoat
+                * gtr1(normal.dot(&h), self.clearcoat_alpha())
+                * f
+                * g.g(normal, wo, wi)
+                / (4.0 * cos_o * cos_i)
+        } else {
+            0.0
+        };
+
+        diffuse + specular + WHITE.scale(clearcoat)
+    }
+
+    pub fn pdf(&self, roughness: f64, normal: Vector3, wo: Vector3, wi: Vector3) -> f64 {
+        if normal.dot(&wo) <= 0.0 || normal.dot(&wi) <= 0.0 {
+            return 0.0;
+        }
+
+        let [diffuse, specular, clearcoat] = self.lobe_weights();
+        let h = (wo + wi).normalize();
+
+        let clearcoat_pdf = if clearcoat > 0.0 {
+            gtr1(normal.dot(&h), self.clearcoat_alpha()) * normal.dot(&h) / (4.0 * wo.dot(&h))
+        } else {
+            0.0
+        };
+
+        diffuse * normal.dot(&wi) / PI
+            + specular * Ggx::from_roughness(roughness).pdf_reflection(normal, wo, wi)
+            + clearcoat * clearcoat_pdf
+    }
+
+    // ローブを一つ選んで方向をサンプリングし、f * cosθ / pdf とともに返す
+    pub fn sample(
+        &self,
+        base: Spectrum,
+        roughness: f64,
+        normal: Vector3,
+        wo: Vector3,
+    ) -> Option<(Vector3, Spectrum)> {
+        let [diffuse, specular, _] = self.lobe_weights();
+        let t = random(0.0, 1.0);
+
+        let wi = if t < diffuse {
+            sample_cosine(normal)
+        } else if t < diffuse + specular {
+            let h = Ggx::from_roughness(roughness).sample_h(normal);
+            (-wo).reflect(&h)
+        } else {
+            let h = sample_gtr1(normal, self.clearcoat_alpha());
+            (-wo).reflect(&h)
+        };
+
+        let pdf = self.pdf(roughness, normal, wo, wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        let f = self.eval(base, roughness, normal, wo, wi);
+        Some((wi, f.scale(normal.dot(&wi) / pdf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_specular_is_ior_1_5() {
+        assert!((Principled::default().ior() - 1.5).abs() < 1e-12);
+
+        // 屈折率から求めた垂直入射の反射率が 0.08 * specular に戻る
+        let p = Principled {
+            specular: 0.8,
+            ..Principled::default()
+        };
+        let f0 = ((p.ior() - 1.0) / (p.ior() + 1.0)).powi(2);
+        assert!((f0 - 0.08 * p.specular).abs() < 1e-12);
+    }
+}
@@ -1,11 +1,21 @@
 use crate::vector::Vector3;
 
-const EPSILON: f64 = 0.001;
+pub const EPSILON: f64 = 0.001;
+
+// 隣のピクセルを通るレイ (テクスチャのフィルタリングに使う)
+#[derive(Clone, Copy)]
+pub struct Differentials {
+    pub rx_origin: Vector3,
+    pub rx_dir: Vector3,
+    pub ry_origin: Vector3,
+    pub ry_dir: Vector3,
+}
 
 #[derive(Clone)]
 pub struct Ray {
     pub origin: Vector3,
     pub dir: Vector3,
+    pub differentials: Option<Differentials>,
 }
 
 impl Ray {
@@ -15,6 +25,14 @@ impl Ray {
         Self {
             origin,
             dir: dir.normalize(),
+            differentials: None,
+        }
+    }
+
+    pub fn with_differentials(self, differentials: Option<Differentials>) -> Self {
+        Self {
+            differentials,
+            ..self
         }
     }
 }
@@ -0,0 +1,229 @@
+use crate::random;
+use crate::spectrum::Spectrum;
+use crate::vector::Vector3;
+
+// 一次交点で一から作る候補の数
+pub const CANDIDATES: usize = 8;
+// 前のサンプルから引き継ぐ候補の数の上限 (古いサンプルに引きずられないように)
+pub const HISTORY_LIMIT: f64 = 20.0 * CANDIDATES as f64;
+// 前のサンプルから使う近傍のピクセルの数
+pub const SPATIAL_NEIGHBOURS: usize = 3;
+
+// 光源上の点
+#[derive(Clone, Copy)]
+pub struct LightSample {
+    pub object: usize,
+    pub point: Vector3,
+    pub normal: Vector3,
+    pub emissive: Spectrum, // テクスチャを評価した RGB の値
+}
+
+// 重み付きリザーバサンプリングで一つだけ残した光源上の点
+#[derive(Clone, Copy, Default)]
+pub struct Reservoir {
+    pub sample: Option<LightSample>,
+    pub weight_sum: f64,
+    pub count: f64,                          // これまでに見た候補の数 M
+    pub weight: f64,                         // 残した点の確率密度の逆数の見積もり W
+    pub surface: Option<(Vector3, Vector3)>, // 作ったときの一次交点の位置と法線
+}
+
+impl Reservoir {
+    pub fn update(&mut self, sample: LightSample, weight: f64, count: f64) {
+        self.weight_sum += weight;
+        self.count += count;
+        if weight > 0.0 && random(0.0, 1.0) * self.weight_sum < weight {
+            self.sample = Some(sample);
+        }
+    }
+
+    // 他のリザーバに取り込まれるときの候補の数
+    pub fn history(&self) -> f64 {
+        self.count.min(HISTORY_LIMIT)
+    }
+
+    // 残した点の確率密度の逆数の見積もり W を求める (候補の重みは候補の数で割ってあるものとする)
+    pub fn finalize(&mut self, target: f64) {
+        self.weight = if target > 0.0 {
+            self.weight_sum / target
+        } else {
+            0.0
+        };
+    }
+
+    // 近傍の交点が同じような面の上にあるか (違う面の点を使うと偏りが大きくなる)
+    pub fn is_similar(&self, point: Vector3, normal: Vector3) -> bool {
+        match self.surface {
+            Some((p, n)) => {
+                let d = p - point;
+                n.dot(&normal) > 0.9 && d.dot(&normal).abs() <= 0.1 * d.len()
+            }
+            None => false,
+        }
+    }
+}
+
+// いくつかのリザーバを surface での一つのリザーバにまとめる。それぞれの点の重みは、その点を作り得た
+// リザーバの間で候補の数と target の積に比例して分け合う (balance heuristic)。
+// target_at(surface, s) は surface で作ったリザーバでの s の target (の見積もり)
+pub fn combine(
+    inputs: &[Reservoir],
+    surface: (Vector3, Vector3),
+    target_at: impl Fn((Vector3, Vector3), &LightSample) -> f64,
+) -> Reservoir {
+    let share = |r: &Reservoir, s: &LightSample| {
+        r.surface
+            .map_or(0.0, |surface| r.history() * target_at(surface, s))
+    };
+
+    let mut result = Reservoir {
+        surface: Some(surface),
+        ..Reservoir::default()
+    };
+    for r in inputs {
+        let s = match r.sample {
+            Some(s) => s,
+            None => {
+                result.count += r.history();
+                continue;
+            }
+        };
+
+        let total: f64 = inputs.iter().map(|x| share(x, &s)).sum();
+        let mis = if total > 0.0 {
+            share(r, &s) / total
+        } else {
+            0.0
+        };
+        result.update(s, mis * target_at(surface, &s) * r.weight, r.history());
+    }
+
+    if let Some(s) = result.sample {
+        result.finalize(target_at(surface, &s));
+    }
+    result
+}
+
+// 一次交点での直接光を求めるときに使う、前のサンプルの同じピクセルと近傍のピクセルのリザーバ
+#[derive(Default)]
+pub struct DirectReuse {
+    pub previous: Reservoir,
+    pub neighbours: [Reservoir; SPATIAL_NEIGHBOURS],
+    pub result: Reservoir, // 新しい候補と同じピクセルのリザーバだけから作ったもの (次のサンプルで使う)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(x: f64) -> LightSample {
+        LightSample {
+            object: 0,
+            point: Vector3 { x, y: 0.0, z: 0.0 },
+            normal: Vector3::default(),
+            emissive: Spectrum::default(),
+        }
+    }
+
+    #[test]
+    fn update_accumulates_weights_and_counts() {
+        let mut r = Reservoir::default();
+        r.update(sample(0.0), 2.0, 1.0);
+        r.update(sample(1.0), 0.0, 1.0);
+        r.update(sample(2.0), 6.0, 1.0);
+
+        assert_eq!(r.weight_sum, 8.0);
+        assert_eq!(r.count, 3.0);
+        assert!(r.sample.is_some_and(|s| s.point.x != 1.0));
+    }
+
+    #[test]
+    fn update_keeps_samples_in_proportion_to_weight() {
+        let n = 100_000;
+        let mut first = 0;
+        for _ in 0..n {
+            let mut r = Reservoir::default();
+            r.update(sample(0.0), 1.0, 1.0);
+            r.update(sample(1.0), 3.0, 1.0);
+            if r.sample.unwrap().point.x == 0.0 {
+                first += 1;
+            }
+        }
+
+        let fraction = first as f64 / n as f64;
+        assert!((fraction - 0.25).abs() < 0.01, "{}", fraction);
+    }
+
+    #[test]
+    fn finalize_divides_by_target() {
+        let mut r = Reservoir::default();
+        r.update(sample(0.0), 1.5, 1.0);
+        r.update(sample(1.0), 1.5, 1.0);
+
+        r.finalize(2.0);
+        assert_eq!(r.weight, 1.5);
+
+        r.finalize(0.0);
+        assert_eq!(r.weight, 0.0);
+    }
+
+    // 交点 (x, 0, 0) での target。x が負なら区間の途中から 0 になる
+    fn target_at((p, _): (Vector3, Vector3), s: &LightSample) -> f64 {
+        (1.0 + p.x * s.point.x).max(0.0)
+    }
+
+    fn surface(x: f64) -> (Vector3, Vector3) {
+        (sample(x).point, Vector3::default())
+    }
+
+    // [0, 1] から一様に選んだ候補で作るリザーバ
+    fn candidates(at: f64, count: usize) -> Reservoir {
+        let mut r = Reservoir {
+            surface: Some(surface(at)),
+            ..Reservoir::default()
+        };
+        for _ in 0..count {
+            let s = sample(random(0.0, 1.0));
+            r.update(s, target_at(surface(at), &s) / count as f64, 1.0);
+        }
+        if let Some(s) = r.sample {
+            r.finalize(target_at(surface(at), &s));
+        }
+        r
+    }
+
+    #[test]
+    fn combine_keeps_a_single_reservoir() {
+        let r = candidates(1.0, 4);
+        let combined = combine(&[r], surface(1.0), target_at);
+        assert_eq!(combined.count, r.count);
+        assert!((combined.weight - r.weight).abs() < 1e-12);
+    }
+
+    #[test]
+    fn combine_is_unbiased() {
+        // 1 + x を [0, 1] で積分すると 1.5
+        let n = 100_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let inputs = [candidates(1.0, 2), candidates(3.0, 4), candidates(-2.0, 3)];
+            let r = combine(&inputs, surface(1.0), target_at);
+            if let Some(s) = r.sample {
+                sum += target_at(surface(1.0), &s) * r.weight;
+            }
+        }
+
+        let mean = sum / n as f64;
+        assert!((mean - 1.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn combine_caps_history() {
+        let mut old = candidates(1.0, 1);
+        old.count = 10.0 * HISTORY_LIMIT;
+        assert_eq!(old.history(), HISTORY_LIMIT);
+
+        let combined = combine(&[old, Reservoir::default()], surface(1.0), target_at);
+        assert_eq!(combined.count, HISTORY_LIMIT);
+    }
+}
@@ -1,138 +1,1235 @@
 use crate::intersect::{Intersectable, Intersection};
-use crate::material::Material;
+use crate::light_tree::{LightBounds, LightTree};
+use crate::material::{fresnel_dielectric, Material};
+use crate::medium::{Interior, Medium, MediumSample, MediumStack};
+use crate::microfacet::Ggx;
 use crate::random;
-use crate::ray::Ray;
+use crate::ray::{self, Ray};
+use crate::restir::{self, DirectReuse, LightSample, Reservoir};
 use crate::spectrum::{self, Spectrum, BLACK};
+use crate::texture::TexturePoint;
 use crate::vector::Vector3;
+use crate::volume::GridVolume;
+use once_cell::sync::OnceCell;
+use std::borrow::Cow;
+use std::cell::RefCell;
 
 const RECURSION_LIMIT: u32 = 10000;
+const ROULETTE_DEPTH: u32 = 5; // これより深いパスはロシアンルーレットで打ち切る
 const VACUUM_REFRACTIVE_INDEX: f64 = 1.0;
+const ATMOSPHERE_DISTANCE: f64 = 1000.0; // 何にも当たらないレイが空に届くまでに大気中を進む距離
+
+// パスに沿って引き継ぐ状態
+#[derive(Clone, Copy, Default)]
+struct Path {
+    stack: MediumStack,
+    wavelength: Option<f64>, // スペクトルモードでサンプリングした波長 [nm]
+}
+
+impl Path {
+    fn with_stack(&self, stack: MediumStack) -> Self {
+        Self {
+            stack,
+            wavelength: self.wavelength,
+        }
+    }
+
+    // スペクトルモードでは RGB の値をこのパスの波長での値に置き換える
+    fn color(&self, c: Spectrum) -> Spectrum {
+        self.wavelength.map_or(c, |w| c.monochrome(w))
+    }
+
+    fn medium(&self, m: Medium) -> Medium {
+        self.wavelength.map_or(m, |w| m.monochrome(w))
+    }
+
+    // テクスチャを評価する必要も波長を選ぶ必要もなければ元のマテリアルをそのまま使う
+    fn material<'a>(&self, m: &'a Material, p: &TexturePoint) -> Cow<'a, Material> {
+        match self.wavelength {
+            Some(w) => Cow::Owned(m.evaluate(p).monochrome(w)),
+            None if m.has_textures() => Cow::Owned(m.evaluate(p)),
+            None => Cow::Borrowed(m),
+        }
+    }
+
+    fn interior_medium(&self, m: &Material) -> Option<Medium> {
+        match self.wavelength {
+            Some(w) => m
+                .subsurface
+                .map(|s| s.monochrome(w).medium())
+                .or(m.medium.map(|m| m.monochrome(w))),
+            None => m.interior_medium(),
+        }
+    }
+}
+
+// 光源も直接サンプリングした散乱 (BSDF で選んだ方向で光源に当たったときの MIS の重みに使う)
+#[derive(Clone, Copy)]
+struct LightSampling {
+    origin: Vector3,
+    bsdf_pdf: f64,
+}
+
+// 表面での直接光の求め方
+#[derive(Clone, Copy)]
+enum DirectLighting<'a> {
+    Off,
+    Sample,                             // 光源を一つサンプリングし、MIS で混ぜる
+    Resample(&'a RefCell<DirectReuse>), // 一次交点で前のサンプルのリザーバを再利用する
+}
+
+// 表面で散乱した次のレイ
+struct Bounce {
+    ray: Ray,
+    weight: Spectrum,
+    path: Option<Path>, // 媒質が変わる場合だけ
+    direct: Spectrum,   // 光源を直接サンプリングして求めた寄与
+    light_sampling: Option<LightSampling>,
+}
+
+impl Bounce {
+    fn new(ray: Ray, weight: Spectrum) -> Self {
+        Self {
+            ray,
+            weight,
+            path: None,
+            direct: BLACK,
+            light_sampling: None,
+        }
+    }
+}
 
 pub struct Scene {
     objects: Vec<Box<dyn Intersectable + 'static>>,
+    lights: Vec<usize>, // 直接サンプリングできる発光物体 (昇順)
+    light_bounds: Vec<LightBounds>,
+    light_tree: OnceCell<LightTree>, // 最初に使うときに作る
     sky_color: Spectrum,
+    atmosphere: Option<Medium>,
+    volumes: Vec<GridVolume>,
+    spectral: bool,
 }
 
 impl Scene {
     pub fn new() -> Self {
         Self {
             objects: vec![],
+            lights: vec![],
+            light_bounds: vec![],
+            light_tree: OnceCell::new(),
             sky_color: BLACK,
+            atmosphere: None,
+            volumes: vec![],
+            spectral: false,
         }
     }
 
     pub fn add_object(&mut self, o: impl Intersectable + 'static) {
+        if let Some(b) = o.light_bounds() {
+            self.lights.push(self.objects.len());
+            self.light_bounds.push(b);
+            self.light_tree = OnceCell::new();
+        }
         self.objects.push(Box::new(o));
     }
 
+    pub fn add_volume(&mut self, v: GridVolume) {
+        self.volumes.push(v);
+    }
+
     pub fn set_sky_color(&mut self, c: Spectrum) {
         self.sky_color = c;
     }
 
+    pub fn set_atmosphere(&mut self, m: Medium) {
+        self.atmosphere = Some(m);
+    }
+
+    pub fn set_spectral(&mut self, enabled: bool) {
+        self.spectral = enabled;
+    }
+
+    fn refractive_index(stack: &MediumStack) -> f64 {
+        stack.current().map_or(VACUUM_REFRACTIVE_INDEX, |i| i.ior)
+    }
+
+    // 鏡面反射 (マイクロファセット分布があればその法線で反射する)。G の重みとハーフベクトルも返す
+    fn reflect(
+        ray: &Ray,
+        intersection: &Intersection,
+        normal: Vector3,
+        shading: Vector3,
+        microfacet: Option<Ggx>,
+    ) -> Option<(Ray, f64, Vector3)> {
+        let point = intersection.point;
+
+        // 陰影計算用の法線で選んだ方向が幾何的な法線で見て逆側なら光が漏れるので捨てる
+        let ggx = match microfacet {
+            Some(ggx) => ggx,
+            None => {
+                let r = ray.dir.reflect(&shading);
+                if r.dot(&normal) <= 0.0 {
+                    return None;
+                }
+                let reflected = Ray::new(point, r)
+                    .with_differentials(intersection.reflect_differentials(ray, shading, r));
+                return Some((reflected, 1.0, shading));
+            }
+        };
+
+        let (r, weight) = ggx.sample_reflection(ray.dir, shading)?;
+        if r.dot(&normal) <= 0.0 {
+            return None;
+        }
+        Some((Ray::new(point, r), weight, (r - ray.dir).normalize()))
+    }
+
     fn interact_surface(
         &self,
-        ray_dir: Vector3,
-        point: Vector3,
-        normal: Vector3,
-        material: Material,
-        eta: f64,
-        depth: u32,
-    ) -> Spectrum {
-        let ks = material.reflective;
-        let kt = material.refractive;
+        ray: &Ray,
+        intersection: &Intersection,
+        material: &Material,
+        object: usize,
+        path: &Path,
+        direct: DirectLighting,
+    ) -> Option<Bounce> {
+        let coat = match material.coating {
+            Some(c) => c,
+            None => return self.interact_base(ray, intersection, material, object, path, direct),
+        };
+
+        let entering = intersection.normal.dot(&ray.dir) < 0.0;
+        let (normal, shading) = if entering {
+            (intersection.normal, intersection.shading_normal)
+        } else {
+            (-intersection.normal, -intersection.shading_normal)
+        };
+
+        // コートの表面で反射するか、透過して下の材質で散乱する
+        let eta = Self::refractive_index(&path.stack) / coat.ior;
+        let cos_o = -ray.dir.dot(&shading);
+        if random(0.0, 1.0) < fresnel_dielectric(cos_o, eta) {
+            let ggx = (coat.roughness > 0.0).then(|| Ggx::from_roughness(coat.roughness));
+            let (ray, weight, _) = Self::reflect(ray, intersection, normal, shading, ggx)?;
+            return Some(Bounce::new(
+                ray,
+                Spectrum {
+                    r: weight,
+                    g: weight,
+                    b: weight,
+                },
+            ));
+        }
+
+        // コートの下では光源を直接サンプリングしない
+        let mut bounce = self.interact_base(
+            ray,
+            intersection,
+            material,
+            object,
+            path,
+            DirectLighting::Off,
+        )?;
+
+        // コートの中を通った距離に応じて吸収され、外へ出るときにも一部が反射して失われる
+        let refracted_cos = |cos: f64| (1.0 - eta * eta * (1.0 - cos * cos)).max(1e-4).sqrt();
+        let cos_i = bounce.ray.dir.dot(&shading);
+        let (length, transmittance) = if cos_i > 0.0 {
+            (
+                1.0 / refracted_cos(cos_o) + 1.0 / refracted_cos(cos_i),
+                1.0 - fresnel_dielectric(cos_i, eta),
+            )
+        } else {
+            (1.0 / refracted_cos(cos_o), 1.0)
+        };
+
+        let absorption = path.color(coat.absorption);
+        bounce.weight = bounce.weight
+            * absorption
+                .map(|a| (-a * coat.thickness * length).exp())
+                .scale(transmittance);
+        Some(bounce)
+    }
+
+    fn interact_base(
+        &self,
+        ray: &Ray,
+        intersection: &Intersection,
+        material: &Material,
+        object: usize,
+        path: &Path,
+        direct: DirectLighting,
+    ) -> Option<Bounce> {
+        let point = intersection.point;
+        let ray_dir = ray.dir;
+
+        // 裏側から当たった場合は物体の内部から外へ出る
+        let entering = intersection.normal.dot(&ray_dir) < 0.0;
+        let (normal, shading) = if entering {
+            (intersection.normal, intersection.shading_normal)
+        } else {
+            (-intersection.normal, -intersection.shading_normal)
+        };
+        let same_side = |r: Vector3| r.dot(&normal) > 0.0;
+        let wo = -ray_dir;
+
+        // BSDF の値と確率密度がわかる散乱では光源も直接サンプリングし、MIS で混ぜる。
+        // サンプリングした方向が捨てられても直接光は残す
+        let scatter = |sampled: Option<(Vector3, Spectrum)>,
+                       f: &dyn Fn(Vector3) -> Spectrum,
+                       pdf: &dyn Fn(Vector3) -> f64| {
+            let mut bounce = match sampled {
+                Some((r, weight)) if same_side(r) => Bounce::new(Ray::new(point, r), weight),
+                _ => Bounce::new(Ray::new(point, shading), BLACK),
+            };
+            let f = |wi: Vector3| f(wi).scale(shading.dot(&wi).max(0.0));
+            match direct {
+                _ if self.lights.is_empty() => {}
+                DirectLighting::Off => {}
+                DirectLighting::Sample => {
+                    bounce.direct = self.sample_direct(point, Some(normal), path, &f, pdf);
+                    bounce.light_sampling = Some(LightSampling {
+                        origin: point,
+                        bsdf_pdf: pdf(bounce.ray.dir),
+                    });
+                }
+                DirectLighting::Resample(reuse) => {
+                    // 光源の寄与はすべてリザーバから求めるので、BSDF で光源に当たっても数えない
+                    let mut reuse = reuse.borrow_mut();
+                    bounce.direct = self.resample_direct(point, normal, path, &f, &mut reuse);
+                    bounce.light_sampling = Some(LightSampling {
+                        origin: point,
+                        bsdf_pdf: 0.0,
+                    });
+                }
+            }
+            Some(bounce)
+        };
+
+        if let Some(brdf) = &material.measured {
+            return scatter(
+                brdf.sample(shading, wo).map(|(r, w)| (r, path.color(w))),
+                &|wi| path.color(brdf.eval(shading, wo, wi)),
+                &|wi| brdf.pdf(shading, wo, wi),
+            );
+        }
+
+        // 表面下散乱: 反射しなかった光は拡散的に境界を越え、内部の媒質の中をランダムウォークする
+        if material.subsurface.is_some() {
+            let transmitted = path.with_stack(if entering {
+                path.stack.pushed(Interior::new(object, material))
+            } else {
+                path.stack.removed(object)
+            });
+            let eta =
+                Self::refractive_index(&path.stack) / Self::refractive_index(&transmitted.stack);
+
+            if random(0.0, 1.0) < fresnel_dielectric(-ray_dir.dot(&shading), eta) {
+                let ggx =
+                    (material.roughness > 0.0).then(|| Ggx::from_roughness(material.roughness));
+                let (ray, weight, _) = Self::reflect(ray, intersection, normal, shading, ggx)?;
+                return Some(Bounce::new(
+                    ray,
+                    Spectrum {
+                        r: weight,
+                        g: weight,
+                        b: weight,
+                    },
+                ));
+            }
+
+            let r = (-shading).random_hemisphere();
+            if same_side(r) {
+                return None;
+            }
+            let w = -2.0 * shading.dot(&r);
+            return Some(Bounce {
+                path: Some(transmitted),
+                ..Bounce::new(Ray::new(point, r), Spectrum { r: w, g: w, b: w })
+            });
+        }
+
+        // 金属は reflective に関わらず常に鏡面反射する
+        let ks = if material.principled.is_some() {
+            0.0
+        } else if material.conductor.is_some() {
+            1.0
+        } else {
+            material.reflective
+        };
+        let kt = material.transmission();
 
         let t = random(0.0, 1.0);
 
         if t < ks {
-            // 鏡面反射
-            let r = ray_dir.reflect(&normal);
-            self.trace(Ray::new(point, r), depth + 1) * material.diffuse
+            // 金属はフレネル反射率、それ以外は diffuse で色が付く
+            let outside = Self::refractive_index(&path.stack);
+            let tint = |h: Vector3| match (material.conductor, material.thin_film) {
+                (Some(c), Some(film)) => {
+                    film.reflectance(wo.dot(&h), outside, c.eta, c.k, path.wavelength)
+                }
+                (Some(c), None) => c.fresnel(wo.dot(&h), outside),
+                (None, _) => material.diffuse,
+            };
+
+            let ggx = (material.roughness > 0.0).then(|| {
+                Ggx::anisotropic(
+                    material.roughness,
+                    material.anisotropy,
+                    intersection.tangent,
+                )
+            });
+            let (ray, weight, h) = Self::reflect(ray, intersection, normal, shading, ggx)?;
+            Some(Bounce::new(ray, tint(h).scale(weight)))
+        } else if t < ks + kt && material.thin_walled {
+            // 薄い板: 中での多重反射をまとめた反射率で反射し、残りは曲がらずに抜ける
+            let outside = Self::refractive_index(&path.stack);
+            let cos = -ray_dir.dot(&shading);
+            let reflectance = match material.thin_film {
+                Some(film) => {
+                    let substrate = Spectrum {
+                        r: outside,
+                        g: outside,
+                        b: outside,
+                    };
+                    film.reflectance(cos, outside, substrate, BLACK, path.wavelength)
+                }
+                None => {
+                    let r = fresnel_dielectric(cos, outside / material.ior());
+                    let r = if r < 1.0 { 2.0 * r / (1.0 + r) } else { r };
+                    Spectrum { r, g: r, b: r }
+                }
+            };
+
+            let p = reflectance.average();
+            if random(0.0, 1.0) < p {
+                let (ray, _, _) = Self::reflect(ray, intersection, normal, shading, None)?;
+                return Some(Bounce::new(ray, reflectance.scale(1.0 / p)));
+            }
+
+            Some(Bounce::new(
+                Ray::new(point, ray_dir).with_differentials(ray.differentials),
+                material.diffuse * reflectance.map(|r| 1.0 - r).scale(1.0 / (1.0 - p)),
+            ))
+        } else if t < ks + kt && material.principled.is_some() {
+            // principled の透過は粗い誘電体として、マイクロファセットの法線でフレネル反射するか屈折するかを選ぶ
+            let transmitted = path.with_stack(if entering {
+                path.stack.pushed(Interior::new(object, material))
+            } else {
+                path.stack.removed(object)
+            });
+            let eta =
+                Self::refractive_index(&path.stack) / Self::refractive_index(&transmitted.stack);
+
+            let ggx = (material.roughness > 0.0).then(|| Ggx::from_roughness(material.roughness));
+            let h = ggx.as_ref().map_or(shading, |g| g.sample_h(shading));
+            let (cos_o, cos_h) = (wo.dot(&h), wo.dot(&shading));
+            if cos_o <= 0.0 || cos_h <= 0.0 {
+                return None;
+            }
+
+            // h を D(h) cosθh で選び、反射と屈折をフレネル項の確率で選ぶと
+            // どちらの重みも G |wo・h| / (|wo・n| |h・n|) になる
+            let weight = |wi: Vector3| {
+                ggx.as_ref().map_or(1.0, |g| {
+                    g.g(shading, wo, wi) * cos_o / (cos_h * h.dot(&shading))
+                })
+            };
+
+            if random(0.0, 1.0) < fresnel_dielectric(cos_o, eta) {
+                let r = ray_dir.reflect(&h);
+                if !same_side(r) {
+                    return None;
+                }
+                let differentials = ggx
+                    .is_none()
+                    .then(|| intersection.reflect_differentials(ray, shading, r))
+                    .flatten();
+                let w = weight(r);
+                return Some(Bounce::new(
+                    Ray::new(point, r).with_differentials(differentials),
+                    Spectrum { r: w, g: w, b: w },
+                ));
+            }
+
+            let r = ray_dir.refract(h, eta);
+            if same_side(r) {
+                return None;
+            }
+            let differentials = ggx
+                .is_none()
+                .then(|| intersection.refract_differentials(ray, shading, r, eta))
+                .flatten();
+            Some(Bounce {
+                path: Some(transmitted),
+                ..Bounce::new(
+                    Ray::new(point, r).with_differentials(differentials),
+                    material.diffuse.scale(weight(r)),
+                )
+            })
         } else if t < ks + kt {
-            // 屈折
-            let r = ray_dir.refract(normal, eta);
-            self.trace(Ray::new(point, r), depth + 1) * material.diffuse
+            // 屈折 (全反射した場合は元の媒質に留まる)
+            let transmitted = path.with_stack(if entering {
+                path.stack.pushed(Interior::new(object, material))
+            } else {
+                path.stack.removed(object)
+            });
+            let outside = Self::refractive_index(&path.stack);
+            let inside = Self::refractive_index(&transmitted.stack);
+            let eta = outside / inside;
+
+            // 色は内部での吸収で付ける (吸収がなければ以前のように diffuse で色を付ける)
+            let mut weight = if material.absorption.average() > 0.0 {
+                Spectrum {
+                    r: 1.0,
+                    g: 1.0,
+                    b: 1.0,
+                }
+            } else {
+                material.diffuse
+            };
+
+            // 薄膜があれば干渉で色付いた反射率の分だけ反射する
+            if let Some(film) = material.thin_film {
+                let substrate = Spectrum {
+                    r: inside,
+                    g: inside,
+                    b: inside,
+                };
+                let cos = -ray_dir.dot(&shading);
+                let reflectance = film.reflectance(cos, outside, substrate, BLACK, path.wavelength);
+
+                let p = reflectance.average();
+                if random(0.0, 1.0) < p {
+                    let (ray, _, _) = Self::reflect(ray, intersection, normal, shading, None)?;
+                    return Some(Bounce::new(ray, reflectance.scale(1.0 / p)));
+                }
+                weight = weight * reflectance.map(|r| 1.0 - r).scale(1.0 / (1.0 - p));
+            }
+
+            let r = ray_dir.refract(shading, eta);
+            let refracted = r.dot(&shading) < 0.0;
+            if refracted == same_side(r) {
+                return None;
+            }
+
+            let (next, differentials) = if refracted {
+                (
+                    Some(transmitted),
+                    intersection.refract_differentials(ray, shading, r, eta),
+                )
+            } else {
+                (None, intersection.reflect_differentials(ray, shading, r))
+            };
+            Some(Bounce {
+                path: next,
+                ..Bounce::new(Ray::new(point, r).with_differentials(differentials), weight)
+            })
+        } else if let Some(principled) = &material.principled {
+            let (base, roughness) = (material.diffuse, material.roughness);
+            scatter(
+                principled.sample(base, roughness, shading, wo),
+                &|wi| principled.eval(base, roughness, shading, wo, wi),
+                &|wi| principled.pdf(roughness, shading, wo, wi),
+            )
         } else {
-            let r = normal.random_hemisphere();
-            let li = self.trace(Ray::new(point, r), depth + 1);
+            // 一様にサンプリングしたので f * cosθ / pdf = (diffuse / π) * cosθ * 2π
+            let r = shading.random_hemisphere();
+            scatter(
+                Some((r, material.diffuse.scale(2.0 * shading.dot(&r)))),
+                &|_| material.diffuse.scale(1.0 / std::f64::consts::PI),
+                &|wi| {
+                    if shading.dot(&wi) > 0.0 {
+                        1.0 / (2.0 * std::f64::consts::PI)
+                    } else {
+                        0.0
+                    }
+                },
+            )
+        }
+    }
 
-            let fr = material.diffuse.scale(1.0 / std::f64::consts::PI);
-            let factor = 2.0 * std::f64::consts::PI * normal.dot(&r);
-            (li * fr).scale(factor)
+    // reuse があれば一次交点での直接光をリザーバの再利用 (ReSTIR) で求める
+    pub fn trace(&self, ray: Ray, depth: u32, reuse: Option<&RefCell<DirectReuse>>) -> Spectrum {
+        let direct = reuse.map_or(DirectLighting::Sample, DirectLighting::Resample);
+        if !self.spectral {
+            return self.trace_in(ray, &Path::default(), depth, 1.0, None, direct);
         }
+
+        // 波長を一つ選んでそのパスの放射輝度を求め、RGB に戻す
+        let wavelength = random(spectrum::WAVELENGTH_MIN, spectrum::WAVELENGTH_MAX);
+        let path = Path {
+            wavelength: Some(wavelength),
+            ..Path::default()
+        };
+        let radiance = self.trace_in(ray, &path, depth, 1.0, None, direct).r;
+
+        spectrum::wavelength_to_rgb(wavelength).scale(radiance)
     }
 
-    pub fn trace(&self, ray: Ray, depth: u32) -> Spectrum {
+    // 物体の内部ならその媒質、外なら大気
+    fn current_medium(&self, path: &Path) -> Option<Medium> {
+        match path.stack.current() {
+            Some(i) => path.interior_medium(self.objects[i.object].material()),
+            None => self.atmosphere.map(|m| path.medium(m)),
+        }
+    }
+
+    // light_sampling があれば直前の散乱で光源も直接サンプリングしている
+    // throughput はカメラからここまでに掛かった重み (の平均)。
+    // 何回か散乱した後は、これが小さいパスほど高い確率で打ち切る (ロシアンルーレット)
+    fn trace_in(
+        &self,
+        ray: Ray,
+        path: &Path,
+        depth: u32,
+        throughput: f64,
+        light_sampling: Option<LightSampling>,
+        direct: DirectLighting,
+    ) -> Spectrum {
+        if depth < ROULETTE_DEPTH {
+            return self.trace_step(ray, path, depth, throughput, light_sampling, direct);
+        }
+
+        let survival = throughput.min(0.95);
+        if survival <= 0.0 || random(0.0, 1.0) >= survival {
+            return BLACK;
+        }
+
+        let throughput = throughput / survival;
+        self.trace_step(ray, path, depth, throughput, light_sampling, direct)
+            .scale(1.0 / survival)
+    }
+
+    fn trace_step(
+        &self,
+        ray: Ray,
+        path: &Path,
+        depth: u32,
+        throughput: f64,
+        light_sampling: Option<LightSampling>,
+        direct: DirectLighting,
+    ) -> Spectrum {
         if RECURSION_LIMIT < depth {
             return spectrum::BLACK;
         }
 
-        let intersection = match self.find_nearest_intersection(&ray) {
+        let interior = path
+            .stack
+            .current()
+            .map(|i| self.objects[i.object].material());
+        let medium = self.current_medium(path);
+
+        // 物体の内部を通った距離に応じて吸収される (Beer-Lambert)
+        let absorption = interior.map_or(BLACK, |m| path.color(m.absorption));
+        let beer_lambert = |distance: f64| absorption.map(|a| (-a * distance).exp());
+
+        let intersection = self.find_nearest_intersection(&ray);
+        let surface_distance = intersection.as_ref().map(|(_, i)| i.distance);
+        let collision =
+            self.sample_volume_collision(&ray, surface_distance.unwrap_or(f64::INFINITY));
+
+        let mut weight = Spectrum {
+            r: 1.0,
+            g: 1.0,
+            b: 1.0,
+        };
+
+        // 何にも当たらないレイも、大気の中なら遠くまで進むうちに減衰・散乱する
+        let event_distance = collision
+            .map(|(t, _)| t)
+            .or(surface_distance)
+            .or_else(|| interior.is_none().then_some(ATMOSPHERE_DISTANCE));
+        if let (Some(medium), Some(distance)) = (medium, event_distance) {
+            match medium.sample(distance) {
+                MediumSample::Scattered { distance, weight } => {
+                    let point = ray.origin + ray.dir.scale(distance);
+                    let dir = medium.sample_phase(ray.dir);
+                    let weight = weight * beer_lambert(distance);
+                    let phase = |wi: Vector3| medium.phase(ray.dir, wi);
+                    let (direct, light_sampling) =
+                        self.sample_phase_direct(point, dir, path, &phase);
+                    return (direct
+                        + self.trace_in(
+                            Ray::new(point, dir),
+                            path,
+                            depth + 1,
+                            throughput * weight.average(),
+                            light_sampling,
+                            DirectLighting::Sample,
+                        ))
+                        * weight;
+                }
+                MediumSample::Passed { weight: w } => weight = w,
+            }
+        }
+
+        if let Some((distance, volume)) = collision {
+            // 吸収されれば発光、そうでなければ散乱
+            let point = ray.origin + ray.dir.scale(distance);
+            let albedo = path.color(volume.albedo);
+            let emitted = path.color(volume.emission(point)) * albedo.map(|a| 1.0 - a);
+
+            let dir = volume.sample_phase(ray.dir);
+            let weight = weight * beer_lambert(distance);
+            let phase = |wi: Vector3| volume.phase(ray.dir, wi);
+            let (direct, light_sampling) = self.sample_phase_direct(point, dir, path, &phase);
+            let scattered = (direct
+                + self.trace_in(
+                    Ray::new(point, dir),
+                    path,
+                    depth + 1,
+                    throughput * (weight * albedo).average(),
+                    light_sampling,
+                    DirectLighting::Sample,
+                ))
+                * albedo;
+
+            return (emitted + scattered) * weight;
+        }
+
+        let (object, mut intersection) = match intersection {
             Some(i) => i,
-            None => return self.sky_color,
+            None => return path.color(self.sky_color) * weight,
         };
 
-        let m = intersection.material;
+        intersection.compute_differentials(&ray);
+        let texture_point = intersection.texture_point();
+        let material = path.material(intersection.material, &texture_point);
+        intersection.apply_normal_map();
+        intersection.compute_tangent();
+        let m = &material;
         let dot = intersection.normal.dot(&ray.dir);
+        weight = weight * beer_lambert(intersection.distance);
+
+        // 優先度の低い誘電体との境界は無視して、内外の記録だけ更新する
+        if m.transmission() > 0.0 && !m.thin_walled {
+            let current = path.stack.current();
+            let skip = if dot < 0.0 {
+                if current.is_some_and(|c| m.priority < c.priority) {
+                    Some(path.with_stack(path.stack.pushed(Interior::new(object, m))))
+                } else {
+                    None
+                }
+            } else if current.map(|c| c.object) != Some(object) && path.stack.contains(object) {
+                Some(path.with_stack(path.stack.removed(object)))
+            } else {
+                None
+            };
 
+            if let Some(next) = skip {
+                let ray =
+                    Ray::new(intersection.point, ray.dir).with_differentials(ray.differentials);
+                let throughput = throughput * weight.average();
+                return self.trace_in(ray, &next, depth + 1, throughput, light_sampling, direct)
+                    * weight;
+            }
+        }
+
+        let mut radiance = match self.interact_surface(&ray, &intersection, m, object, path, direct)
+        {
+            Some(b) if b.weight.average() > 0.0 => {
+                let next = b.path.as_ref().unwrap_or(path);
+                b.direct
+                    + self.trace_in(
+                        b.ray,
+                        next,
+                        depth + 1,
+                        throughput * (b.weight * weight).average(),
+                        b.light_sampling,
+                        DirectLighting::Sample,
+                    ) * b.weight
+            }
+            Some(b) => b.direct,
+            None => BLACK,
+        };
+
+        // 直接サンプリングもしている光源の発光は MIS の重みを掛けて数える
         if dot < 0.0 {
-            self.interact_surface(
-                ray.dir,
-                intersection.point,
-                intersection.normal,
-                m,
-                VACUUM_REFRACTIVE_INDEX / m.refractive_index,
-                depth,
-            ) + m.emissive.scale(-dot)
-        } else {
-            self.interact_surface(
-                ray.dir,
-                intersection.point,
-                -intersection.normal,
-                m,
-                m.refractive_index / VACUUM_REFRACTIVE_INDEX,
-                depth,
-            )
+            let mis = match light_sampling {
+                Some(s) => match self.lights.binary_search(&object) {
+                    Ok(i) => {
+                        let light_pdf = self.objects[object].light_pdf(s.origin, &intersection)
+                            * self.light_tree().pmf(s.origin, i);
+                        power_heuristic(s.bsdf_pdf, light_pdf)
+                    }
+                    Err(_) => 1.0,
+                },
+                _ => 1.0,
+            };
+            radiance += m.emissive.scale(-dot * mis);
         }
+
+        radiance * weight
     }
 
-    fn find_nearest_intersection(&self, ray: &Ray) -> Option<Intersection> {
-        assert!(!self.objects.is_empty());
+    // 不透明度に応じて (0 なら必ず) 交点を飛ばし、その先の交点を探す
+    fn intersect_opaque<'a>(object: &'a dyn Intersectable, ray: &Ray) -> Option<Intersection<'a>> {
+        // 完全に不透明な物体なら最初の交点で決まる
+        let material = object.material();
+        if material.opacity >= 1.0 && material.opacity_texture.is_none() {
+            return object.intersect(ray);
+        }
+
+        let mut current = ray.clone();
+        loop {
+            let mut hit = object.intersect(&current)?;
+            if hit.material.stops_at(&hit.texture_point()) {
+                // 元のレイの始点からの距離に直す
+                hit.distance = (hit.point - ray.origin).len();
+                return Some(hit);
+            }
 
+            current = Ray::new(hit.point, ray.dir).with_differentials(ray.differentials);
+        }
+    }
+
+    fn find_nearest_intersection(&self, ray: &Ray) -> Option<(usize, Intersection<'_>)> {
         self.objects
             .iter()
-            .flat_map(|x| x.intersect(ray))
-            .filter(|x| !x.distance.is_nan())
-            .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap())
+            .enumerate()
+            .flat_map(|(i, x)| Self::intersect_opaque(x.as_ref(), ray).map(|x| (i, x)))
+            .filter(|(_, x)| !x.distance.is_nan())
+            .min_by(|(_, a), (_, b)| a.distance.partial_cmp(&b.distance).unwrap())
     }
 
-    fn visible(&self, org: Vector3, target: Vector3) -> bool {
-        let v = (target - org).normalize();
-        let shadow_ray = Ray::new(org, v);
-
-        self.objects
+    fn sample_volume_collision(&self, ray: &Ray, max_distance: f64) -> Option<(f64, &GridVolume)> {
+        self.volumes
             .iter()
-            .flat_map(|x| x.intersect(&shadow_ray))
-            .all(|x| x.distance >= v.len())
+            .flat_map(|v| v.sample_collision(ray, max_distance).map(|t| (t, v)))
+            .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
     }
 
-    fn diffuse_lighting(
+    fn light_tree(&self) -> &LightTree {
+        self.light_tree
+            .get_or_init(|| LightTree::new(&self.light_bounds))
+    }
+
+    // 光源を一つ選び、その上の点からの直接光を求める (f は BSDF の値に cosθ を掛けたもの)
+    // normal がなければ (媒質の中では) どの方向の光源も受ける
+    fn sample_direct(
+        &self,
+        point: Vector3,
+        normal: Option<Vector3>,
+        path: &Path,
+        f: &dyn Fn(Vector3) -> Spectrum,
+        bsdf_pdf: &dyn Fn(Vector3) -> f64,
+    ) -> Spectrum {
+        let (i, pmf) = match self.light_tree().sample(point) {
+            Some(s) => s,
+            None => return BLACK,
+        };
+        let (light, light_pdf) = match self.objects[self.lights[i]].sample_light(point) {
+            Some(s) => s,
+            None => return BLACK,
+        };
+
+        let wi = (light.point - point).normalize();
+        let cos_l = -wi.dot(&light.normal);
+        if normal.is_some_and(|n| wi.dot(&n) <= 0.0) || cos_l <= 0.0 {
+            return BLACK;
+        }
+
+        let f = f(wi);
+        if f.average() <= 0.0 {
+            return BLACK;
+        }
+
+        let pdf = light_pdf * pmf;
+        let emissive = path.color(light.material.emissive_at(&light.texture_point()));
+        let tr = self.transmittance(point, light.point, self.current_medium(path));
+
+        let mis = power_heuristic(pdf, bsdf_pdf(wi));
+        (emissive.scale(cos_l) * f * tr).scale(mis / pdf)
+    }
+
+    // 媒質の中の散乱点でも光源を直接サンプリングし、位相関数で選んだ方向 sampled と MIS で混ぜる。
+    // 位相関数は厳密にサンプリングできるので、その値がそのまま確率密度になる
+    fn sample_phase_direct(
+        &self,
+        point: Vector3,
+        sampled: Vector3,
+        path: &Path,
+        phase: &dyn Fn(Vector3) -> f64,
+    ) -> (Spectrum, Option<LightSampling>) {
+        if self.lights.is_empty() {
+            return (BLACK, None);
+        }
+
+        let f = |wi: Vector3| {
+            let p = phase(wi);
+            Spectrum { r: p, g: p, b: p }
+        };
+        let direct = self.sample_direct(point, None, path, &f, phase);
+        let light_sampling = LightSampling {
+            origin: point,
+            bsdf_pdf: phase(sampled),
+        };
+        (direct, Some(light_sampling))
+    }
+
+    // 光源上の候補をいくつか作り、前のサンプルの同じピクセルと近傍のピクセルのリザーバと合わせて
+    // 一つ選んで直接光を求める
+    fn resample_direct(
         &self,
         point: Vector3,
         normal: Vector3,
-        diffuse_color: Spectrum,
-        light_pos: Vector3,
-        light_power: Spectrum,
+        path: &Path,
+        f: &dyn Fn(Vector3) -> Spectrum,
+        reuse: &mut DirectReuse,
     ) -> Spectrum {
-        let v = light_pos - point;
-        let l = v.normalize();
-        let dot = normal.dot(&l);
+        let surface = (point, normal);
+
+        // surface (交点の位置と法線) での遮蔽を無視した寄与 (面積あたり)。
+        // 近傍の交点でも BSDF はこの交点のもので代用する
+        let contribution_at = |(p, n): (Vector3, Vector3), s: &LightSample| {
+            let d = s.point - p;
+            let wi = d.normalize();
+            let cos_l = -wi.dot(&s.normal);
+            if wi.dot(&n) <= 0.0 || cos_l <= 0.0 {
+                return BLACK;
+            }
+            (path.color(s.emissive) * f(wi)).scale(cos_l * cos_l / d.dot(&d))
+        };
+        let target_at = |surface, s: &LightSample| contribution_at(surface, s).average().max(0.0);
+
+        let mut fresh = Reservoir {
+            surface: Some(surface),
+            ..Reservoir::default()
+        };
+        for _ in 0..restir::CANDIDATES {
+            let candidate = self.light_tree().sample(point).and_then(|(i, pmf)| {
+                let (x, pdf) = self.objects[self.lights[i]].sample_light(point)?;
+                Some((self.lights[i], x, pdf * pmf))
+            });
+            let (object, x, pdf) = match candidate {
+                Some(c) => c,
+                None => {
+                    fresh.count += 1.0;
+                    continue;
+                }
+            };
 
-        if dot > 0.0 && self.visible(point, light_pos) {
-            let r = v.len();
-            let factor = dot / (4.0 * std::f64::consts::PI * r * r);
-            return light_power.scale(factor) * diffuse_color;
+            // 立体角あたりの確率密度を面積あたりに直す
+            let d = x.point - point;
+            let area_pdf = pdf * (-d.normalize().dot(&x.normal)).max(0.0) / d.dot(&d);
+            let sample = LightSample {
+                object,
+                point: x.point,
+                normal: x.normal,
+                emissive: x.material.emissive_at(&x.texture_point()),
+            };
+            let weight = if area_pdf > 0.0 {
+                target_at(surface, &sample) / (area_pdf * restir::CANDIDATES as f64)
+            } else {
+                0.0
+            };
+            fresh.update(sample, weight, 1.0);
         }
+        if let Some(s) = fresh.sample {
+            fresh.finalize(target_at(surface, &s));
+        }
+
+        // 次のサンプルには同じピクセルの履歴だけを残す (自分の点が近傍を通って戻ってこないように)。
+        // 遮られた点も残す (見えるかどうかで選ぶと、使うときに元の交点から見えるかも調べることになる)
+        let similar = |r: &Reservoir| r.is_similar(point, normal).then_some(*r);
+        let history = match similar(&reuse.previous) {
+            Some(previous) => restir::combine(&[fresh, previous], surface, target_at),
+            None => fresh,
+        };
+        reuse.result = history;
+
+        let mut inputs = [history; 1 + restir::SPATIAL_NEIGHBOURS];
+        for (input, r) in inputs[1..].iter_mut().zip(&reuse.neighbours) {
+            *input = similar(r).unwrap_or_default();
+        }
+        let reservoir = restir::combine(&inputs, surface, target_at);
+
+        let sample = match reservoir.sample {
+            Some(s) => s,
+            None => return BLACK,
+        };
+        let tr = self.transmittance(point, sample.point, self.current_medium(path));
+        (contribution_at(surface, &sample) * tr).scale(reservoir.weight)
+    }
+
+    fn transmittance(&self, org: Vector3, target: Vector3, medium: Option<Medium>) -> Spectrum {
+        let distance = (target - org).len();
+        let shadow_ray = Ray::new(org, target - org);
+
+        let blocked = self
+            .objects
+            .iter()
+            .flat_map(|x| Self::intersect_opaque(x.as_ref(), &shadow_ray))
+            .any(|x| x.distance < distance - 2.0 * ray::EPSILON);
+
+        if blocked {
+            return BLACK;
+        }
+
+        let tr = self
+            .volumes
+            .iter()
+            .map(|v| v.transmittance(&shadow_ray, distance))
+            .product::<f64>();
+
+        match medium {
+            Some(m) => m.transmittance(distance).scale(tr),
+            None => Spectrum {
+                r: tr,
+                g: tr,
+                b: tr,
+            },
+        }
+    }
+}
+
+// 二つのサンプリング方法の確率密度から、a で得たサンプルに掛ける重み (べき乗ヒューリスティック)
+fn power_heuristic(a: f64, b: f64) -> f64 {
+    let (a2, b2) = (a * a, b * b);
+    if a2 + b2 <= 0.0 {
+        return 0.0;
+    }
+    a2 / (a2 + b2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Conductor;
+    use crate::mesh::Mesh;
+    use crate::principled::Principled;
+    use crate::sphere::Sphere;
+
+    fn v(x: f64, y: f64, z: f64) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
+    fn gray(x: f64) -> Spectrum {
+        Spectrum { r: x, g: x, b: x }
+    }
+
+    // 床と、一つの球の光源と、影を落とす球
+    fn single_light_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_object(Mesh::quad(
+            v(-3.0, 0.0, -3.0),
+            v(0.0, 0.0, 6.0),
+            v(6.0, 0.0, 0.0),
+            Material {
+                diffuse: gray(0.7),
+                ..Material::default()
+            },
+        ));
+        scene.add_object(Sphere {
+            center: v(0.0, 3.0, 0.0),
+            radius: 0.5,
+            material: Material {
+                emissive: gray(20.0),
+                ..Material::default()
+            },
+        });
+        scene.add_object(Sphere {
+            center: v(0.3, 1.0, 0.0),
+            radius: 0.4,
+            material: Material {
+                diffuse: gray(0.5),
+                ..Material::default()
+            },
+        });
+        scene
+    }
+
+    #[test]
+    fn empty_scene_shows_sky() {
+        let mut scene = Scene::new();
+        scene.set_sky_color(gray(0.25));
+        let color = scene.trace(Ray::new(v(0.0, 0.0, 0.0), v(0.0, 0.0, 1.0)), 0, None);
+        assert_eq!(color.channels(), [0.25; 3]);
+    }
+
+    #[test]
+    fn conductor_reflects_without_reflective() {
+        let mut scene = Scene::new();
+        scene.set_sky_color(gray(1.0));
+        scene.add_object(Sphere {
+            center: v(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material {
+                conductor: Some(Conductor::SILVER),
+                ..Material::default()
+            },
+        });
+
+        let ray = Ray::new(v(0.0, 0.0, -5.0), v(0.0, 0.0, 1.0));
+        let radiance = scene.trace(ray, 0, None);
+        assert!(radiance.average() > 0.8, "{}", radiance.average());
+    }
+
+    #[test]
+    fn default_principled_transmission_bends_rays() {
+        let mut scene = Scene::new();
+        scene.add_object(Sphere {
+            center: v(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material {
+                principled: Some(Principled {
+                    transmission: 1.0,
+                    ..Principled::default()
+                }),
+                ..Material::default()
+            },
+        });
+        let ray = Ray::new(v(0.5, 0.0, -5.0), v(0.0, 0.0, 1.0));
+        let intersection = scene.objects[0].intersect(&ray).unwrap();
+
+        // フレネル反射しなかったものが屈折率 1.5 で曲がる
+        let cos_i = -ray.dir.dot(&intersection.normal);
+        let cos_t = (1.0 - (1.0 - cos_i * cos_i) / (1.5 * 1.5)).sqrt();
+        let mut refracted = 0;
+        for _ in 0..100 {
+            let bounce = scene
+                .interact_surface(
+                    &ray,
+                    &intersection,
+                    intersection.material,
+                    0,
+                    &Path::default(),
+                    DirectLighting::Off,
+                )
+                .unwrap();
+            if bounce.path.is_some() {
+                assert!((-bounce.ray.dir.dot(&intersection.normal) - cos_t).abs() < 1e-9);
+                refracted += 1;
+            }
+        }
+        assert!(refracted > 80);
+    }
+
+    #[test]
+    fn restir_matches_light_sampling_for_single_light() {
+        const SIZE: usize = 12;
+        const SAMPLES: usize = 200;
+
+        let scene = single_light_scene();
+        let eye = v(0.0, 2.5, -4.0);
+        let ray = |i: usize, j: usize| {
+            let x = -2.0 + 4.0 * (i as f64 + random(0.0, 1.0)) / SIZE as f64;
+            let z = -2.0 + 4.0 * (j as f64 + random(0.0, 1.0)) / SIZE as f64;
+            Ray::new(eye, v(x, 0.0, z) - eye)
+        };
+
+        let mut plain = BLACK;
+        let mut resampled = BLACK;
+        let mut reservoirs = vec![Reservoir::default(); SIZE * SIZE];
+        for _ in 0..SAMPLES {
+            let previous = reservoirs.clone();
+            for (index, reservoir) in reservoirs.iter_mut().enumerate() {
+                let (i, j) = (index % SIZE, index / SIZE);
+                plain += scene.trace(ray(i, j), 0, None);
+
+                let mut neighbours = [Reservoir::default(); restir::SPATIAL_NEIGHBOURS];
+                for n in neighbours.iter_mut() {
+                    let other = random(0.0, (SIZE * SIZE) as f64) as usize;
+                    if other != index {
+                        *n = previous[other];
+                    }
+                }
+                let reuse = RefCell::new(DirectReuse {
+                    previous: previous[index],
+                    neighbours,
+                    result: Reservoir::default(),
+                });
+                resampled += scene.trace(ray(i, j), 0, Some(&reuse));
+                *reservoir = reuse.into_inner().result;
+            }
+        }
+
+        let (plain, resampled) = (plain.average(), resampled.average());
+        assert!(
+            (resampled / plain - 1.0).abs() < 0.02,
+            "{} vs {}",
+            resampled,
+            plain
+        );
+    }
+
+    #[test]
+    fn medium_light_sampling_matches_phase_sampling() {
+        const SAMPLES: usize = 200_000;
+
+        let mut scene = Scene::new();
+        scene.add_object(Sphere {
+            center: v(0.0, 2.0, 0.0),
+            radius: 1.5,
+            material: Material {
+                diffuse: BLACK,
+                emissive: gray(1.0),
+                ..Material::default()
+            },
+        });
+        scene.set_atmosphere(Medium {
+            absorption: gray(0.05),
+            scattering: gray(0.3),
+            anisotropy: 0.4,
+        });
+        let ray = || Ray::new(v(-4.0, 0.0, 0.0), v(1.0, 0.0, 0.0));
+        let mean = |scene: &Scene| {
+            let total = (0..SAMPLES).fold(BLACK, |t, _| t + scene.trace(ray(), 0, None));
+            total.average() / SAMPLES as f64
+        };
+
+        let sampled = mean(&scene);
+        // 光源の一覧を空にすると、位相関数のサンプリングだけで光源に当たる
+        scene.lights.clear();
+        let unsampled = mean(&scene);
+        assert!(
+            (sampled / unsampled - 1.0).abs() < 0.03,
+            "{} vs {}",
+            sampled,
+            unsampled
+        );
+    }
+
+    #[test]
+    fn translucent_light_sampling_matches_bsdf_sampling() {
+        const SAMPLES: usize = 100_000;
+
+        let mut scene = Scene::new();
+        scene.add_object(Mesh::quad(
+            v(-3.0, 0.0, -3.0),
+            v(0.0, 0.0, 6.0),
+            v(6.0, 0.0, 0.0),
+            Material {
+                diffuse: gray(0.5),
+                ..Material::default()
+            },
+        ));
+        scene.add_object(Mesh::quad(
+            v(-1.0, 2.0, -1.0),
+            v(2.0, 0.0, 0.0),
+            v(0.0, 0.0, 2.0),
+            Material {
+                emissive: gray(1.0),
+                opacity: 0.5,
+                ..Material::default()
+            },
+        ));
+        let eye = v(0.0, 1.0, -3.0);
+        let ray = || Ray::new(eye, v(random(-1.0, 1.0), 0.0, random(-1.0, 1.0)) - eye);
+        let mean = |scene: &Scene| {
+            let total = (0..SAMPLES).fold(BLACK, |t, _| t + scene.trace(ray(), 0, None));
+            total.average() / SAMPLES as f64
+        };
 
-        spectrum::BLACK
+        let sampled = mean(&scene);
+        scene.lights.clear();
+        let unsampled = mean(&scene);
+        assert!(
+            (sampled / unsampled - 1.0).abs() < 0.03,
+            "{} vs {}",
+            sampled,
+            unsampled
+        );
     }
 }
@@ -1,7 +1,49 @@
+use once_cell::sync::Lazy;
 use std::ops::{Add, AddAssign, Mul};
 
 const DISPLAY_GAMMA: f64 = 2.2;
 
+pub const WAVELENGTH_MIN: f64 = 380.0;
+pub const WAVELENGTH_MAX: f64 = 720.0;
+
+// RGB からスペクトルへのアップサンプリングに使う基底 (Smits 1999)
+// 380nm から 720nm を 10 区間に分けたもの
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// 一様な分光分布が (1, 1, 1) になるようにするための正規化係数。
+// 波長は一様にサンプリングするので、和ではなく平均で割る
+static RGB_NORMALIZATION: Lazy<Spectrum> = Lazy::new(|| {
+    let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+    let sum = (0..steps)
+        .map(|i| {
+            let (x, y, z) = cie_xyz(WAVELENGTH_MIN + i as f64 + 0.5);
+            xyz_to_rgb(x, y, z)
+        })
+        .fold(BLACK, |a, b| a + b);
+
+    sum.map(|x| steps as f64 / x)
+});
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Color {
     pub r: u8,
@@ -32,7 +74,62 @@ impl Spectrum {
         }
     }
 
-    pub fn to_color(&self) -> Color {
+    pub fn map(&self, f: impl Fn(f64) -> f64) -> Spectrum {
+        Spectrum {
+            r: f(self.r),
+            g: f(self.g),
+            b: f(self.b),
+        }
+    }
+
+    pub fn channels(&self) -> [f64; 3] {
+        [self.r, self.g, self.b]
+    }
+
+    pub fn average(&self) -> f64 {
+        (self.r + self.g + self.b) / 3.0
+    }
+
+    // Smits の方法でアップサンプリングしたスペクトルの、ある波長での値
+    pub fn at_wavelength(&self, wavelength: f64) -> f64 {
+        let bin = ((wavelength - WAVELENGTH_MIN) / (WAVELENGTH_MAX - WAVELENGTH_MIN) * 10.0)
+            .clamp(0.0, 9.0) as usize;
+        let (r, g, b) = (self.r, self.g, self.b);
+
+        if r <= g && r <= b {
+            let mut v = r * SMITS_WHITE[bin];
+            if g <= b {
+                v += (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin];
+            } else {
+                v += (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin];
+            }
+            v
+        } else if g <= r && g <= b {
+            let mut v = g * SMITS_WHITE[bin];
+            if r <= b {
+                v += (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin];
+            } else {
+                v += (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin];
+            }
+            v
+        } else {
+            let mut v = b * SMITS_WHITE[bin];
+            if r <= g {
+                v += (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin];
+            } else {
+                v += (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin];
+            }
+            v
+        }
+    }
+
+    // 全チャンネルをある波長での値にしたもの
+    pub fn monochrome(&self, wavelength: f64) -> Spectrum {
+        let v = self.at_wavelength(wavelength);
+        Spectrum { r: v, g: v, b: v }
+    }
+
+    pub fn to_color(self) -> Color {
         let convert = |x: f64| x.powf(1.0 / DISPLAY_GAMMA).mul(255.).min(255.) as u8;
 
         Color {
@@ -74,3 +171,102 @@ impl Mul for Spectrum {
         }
     }
 }
+
+// CIE 1931 等色関数の多峰ガウス近似 (Wyman et al. 2013)
+pub fn cie_xyz(wavelength: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let t = (wavelength - mu) / if wavelength < mu { s1 } else { s2 };
+        (-0.5 * t * t).exp()
+    };
+
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+
+    (x, y, z)
+}
+
+// XYZ からリニア sRGB へ
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> Spectrum {
+    Spectrum {
+        r: 3.2406 * x - 1.5372 * y - 0.4986 * z,
+        g: -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        b: 0.0557 * x - 0.2040 * y + 1.0570 * z,
+    }
+}
+
+// 黒体放射の色 (輝度 Y = 1 に正規化)
+pub fn blackbody(temperature: f64) -> Spectrum {
+    const H: f64 = 6.626_070_15e-34;
+    const C: f64 = 299_792_458.0;
+    const K: f64 = 1.380_649e-23;
+
+    if temperature <= 0.0 {
+        return BLACK;
+    }
+
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for i in 0..=80 {
+        let wavelength = 380.0 + 5.0 * i as f64;
+        let l = wavelength * 1e-9;
+        let power = 2.0 * H * C * C / (l.powi(5) * ((H * C / (l * K * temperature)).exp() - 1.0));
+        let (cx, cy, cz) = cie_xyz(wavelength);
+        x += cx * power;
+        y += cy * power;
+        z += cz * power;
+    }
+
+    if y <= 0.0 {
+        return BLACK;
+    }
+
+    xyz_to_rgb(x / y, 1.0, z / y).map(|c| c.max(0.0))
+}
+
+// 一様にサンプリングした波長での放射輝度 1 に対応する RGB (確率密度で割ったもの)
+pub fn wavelength_to_rgb(wavelength: f64) -> Spectrum {
+    let (x, y, z) = cie_xyz(wavelength);
+    xyz_to_rgb(x, y, z) * *RGB_NORMALIZATION
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random;
+
+    #[test]
+    fn flat_spectrum_averages_to_white() {
+        let n = 100_000;
+        let sum = (0..n)
+            .map(|i| {
+                let u = (i as f64 + 0.5) / n as f64;
+                wavelength_to_rgb(WAVELENGTH_MIN + u * (WAVELENGTH_MAX - WAVELENGTH_MIN))
+            })
+            .fold(BLACK, |a, b| a + b);
+        let mean = sum.scale(1.0 / n as f64);
+
+        assert!((mean.r - 1.0).abs() < 1e-3, "r = {}", mean.r);
+        assert!((mean.g - 1.0).abs() < 1e-3, "g = {}", mean.g);
+        assert!((mean.b - 1.0).abs() < 1e-3, "b = {}", mean.b);
+    }
+
+    #[test]
+    fn random_wavelengths_average_to_white() {
+        let n = 200_000;
+        let sum = (0..n)
+            .map(|_| wavelength_to_rgb(random(WAVELENGTH_MIN, WAVELENGTH_MAX)))
+            .fold(BLACK, |a, b| a + b);
+        let mean = sum.scale(1.0 / n as f64);
+
+        assert!((mean.average() - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn cie_y_peaks_near_555nm() {
+        let (_, y, _) = cie_xyz(555.0);
+        assert!((y - 1.0).abs() < 0.05);
+        assert!(cie_xyz(400.0).1 < 0.01);
+        assert!(cie_xyz(700.0).1 < 0.01);
+    }
+}
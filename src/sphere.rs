@@ -1,6 +1,8 @@
-use crate::intersect::{Intersectable, Intersection};
+use crate::intersect::{Intersectable, Intersection, SurfaceDifferentials};
+use crate::light_tree::LightBounds;
 use crate::material::Material;
-use crate::ray::Ray;
+use crate::random;
+use crate::ray::{self, Ray};
 use crate::vector::Vector3;
 
 pub struct Sphere {
@@ -9,8 +11,45 @@ pub struct Sphere {
     pub material: Material,
 }
 
+impl Sphere {
+    // spherical_uv の u, v で偏微分したもの (p は中心からの位置)
+    fn tangents(&self, p: Vector3) -> (Vector3, Vector3) {
+        let pi = std::f64::consts::PI;
+        let dpdu = Vector3 {
+            x: -p.z,
+            y: 0.0,
+            z: p.x,
+        }
+        .scale(2.0 * pi);
+
+        let r_xz = (p.x * p.x + p.z * p.z).sqrt().max(1e-12);
+        let cos_theta = p.y / self.radius;
+        let dpdv = Vector3 {
+            x: cos_theta * p.x / r_xz * self.radius,
+            y: -r_xz,
+            z: cos_theta * p.z / r_xz * self.radius,
+        }
+        .scale(pi);
+
+        (dpdu, dpdv)
+    }
+
+    // point から見た球が張る円錐の 1 - cosθmax (内側なら None)
+    fn cone(&self, point: Vector3) -> Option<f64> {
+        let d = self.center - point;
+        let sin2_max = self.radius * self.radius / d.dot(&d);
+        if sin2_max >= 1.0 {
+            return None;
+        }
+
+        // 小さく見える球で桁落ちしないように sin から求める
+        let cos_max = (1.0 - sin2_max).sqrt();
+        Some(sin2_max / (1.0 + cos_max))
+    }
+}
+
 impl Intersectable for Sphere {
-    fn intersect(&self, ray: &Ray) -> Option<Intersection> {
+    fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
         let v = ray.origin - self.center;
         let b = ray.dir.dot(&v);
         let c = v.dot(&v) - (self.radius * self.radius);
@@ -27,15 +66,83 @@ impl Intersectable for Sphere {
             if 0.0 < t {
                 let point = ray.origin + ray.dir.scale(t);
                 let normal = (point - self.center).normalize();
+                let (dpdu, dpdv) = self.tangents(point - self.center);
+
                 return Some(Intersection {
                     distance: t,
                     point,
                     normal,
-                    material: self.material.clone(),
+                    shading_normal: normal,
+                    tangent: Vector3::default(),
+                    uv: spherical_uv(normal),
+                    dpdu,
+                    dpdv,
+                    dndu: dpdu.scale(1.0 / self.radius),
+                    dndv: dpdv.scale(1.0 / self.radius),
+                    material: &self.material,
+                    differentials: SurfaceDifferentials::default(),
                 });
             }
         }
 
         None
     }
+
+    fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn light_bounds(&self) -> Option<LightBounds> {
+        if !self.material.is_emissive() {
+            return None;
+        }
+
+        let r = Vector3 {
+            x: self.radius,
+            y: self.radius,
+            z: self.radius,
+        };
+        let area = 4.0 * std::f64::consts::PI * self.radius * self.radius;
+        Some(LightBounds {
+            min: self.center - r,
+            max: self.center + r,
+            power: self.material.emissive.average() * area,
+        })
+    }
+
+    // point から球が張る円錐の中で一様に方向を選ぶ
+    fn sample_light(&self, point: Vector3) -> Option<(Intersection<'_>, f64)> {
+        let one_minus_cos = self.cone(point)?;
+
+        let cos = 1.0 - random(0.0, 1.0) * one_minus_cos;
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = random(0.0, 2.0 * std::f64::consts::PI);
+
+        let w = (self.center - point).normalize();
+        let (t, b) = w.orthonormal_basis();
+        let dir = w.scale(cos) + t.scale(sin * phi.cos()) + b.scale(sin * phi.sin());
+
+        let pdf = 1.0 / (2.0 * std::f64::consts::PI * one_minus_cos);
+        // 交差判定と同じく、不透明度に応じて素通りする点は光らない
+        let x = self.intersect(&Ray::new(point, dir))?;
+        if !self.material.stops_at(&x.texture_point()) {
+            return None;
+        }
+
+        // 影の光線が光源自身に当たらないように、他の光線と同じだけ表面から離す
+        let point = x.point + x.normal.scale(ray::EPSILON);
+        Some((Intersection { point, ..x }, pdf))
+    }
+
+    fn light_pdf(&self, point: Vector3, _x: &Intersection) -> f64 {
+        self.cone(point)
+            .map_or(0.0, |c| 1.0 / (2.0 * std::f64::consts::PI * c))
+    }
+}
+
+// 経度を u、極 (+y) からの角度を v とする
+fn spherical_uv(n: Vector3) -> (f64, f64) {
+    let u = 0.5 + n.z.atan2(n.x) / (2.0 * std::f64::consts::PI);
+    let v = n.y.clamp(-1.0, 1.0).acos() / std::f64::consts::PI;
+    (u, v)
 }
@@ -0,0 +1,486 @@
+use crate::spectrum::{Spectrum, BLACK};
+use crate::vector::Vector3;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// テクスチャを評価する点
+#[derive(Clone, Copy, Default)]
+pub struct TexturePoint {
+    pub point: Vector3,
+    pub normal: Vector3,
+    pub u: f64,
+    pub v: f64,
+
+    // 隣のピクセルに移ったときの変化量 (フィルタリング用、0 なら点サンプリング)
+    pub dpdx: Vector3,
+    pub dpdy: Vector3,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+}
+
+pub trait Texture: Send + Sync {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum;
+
+    // 反射率や粗さなどスカラー値のチャンネルに使うとき
+    fn value(&self, p: &TexturePoint) -> f64 {
+        self.evaluate(p).average()
+    }
+
+    fn alpha(&self, _: &TexturePoint) -> f64 {
+        1.0
+    }
+}
+
+impl Texture for Spectrum {
+    fn evaluate(&self, _: &TexturePoint) -> Spectrum {
+        *self
+    }
+}
+
+// 空間上のチェック柄
+pub struct Checker {
+    pub even: Arc<dyn Texture>,
+    pub odd: Arc<dyn Texture>,
+    pub grid_width: f64,
+}
+
+// 偶数のマスで 1、奇数のマスで -1 になる矩形波の積分 (三角波)
+fn square_wave_integral(t: f64) -> f64 {
+    let m = (t + 0.5).rem_euclid(2.0);
+    if m < 1.0 {
+        m - 0.5
+    } else {
+        1.5 - m
+    }
+}
+
+impl Checker {
+    // フットプリントを囲む箱の中での矩形波の平均
+    fn average_wave(&self, x: f64, dx: f64, dy: f64) -> f64 {
+        let half = dx.abs().max(dy.abs());
+        let w = self.grid_width;
+
+        if half * 1e3 < w {
+            return if (x / w).round() % 2.0 == 0.0 {
+                1.0
+            } else {
+                -1.0
+            };
+        }
+
+        let a = (x - half) / w;
+        let b = (x + half) / w;
+        (square_wave_integral(b) - square_wave_integral(a)) / (b - a)
+    }
+}
+
+impl Texture for Checker {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        // 各軸の矩形波の積が +1 なら偶数のマス。箱フィルタで積分すると軸ごとに分離できる
+        let wave = self.average_wave(p.point.x, p.dpdx.x, p.dpdy.x)
+            * self.average_wave(p.point.y, p.dpdx.y, p.dpdy.y)
+            * self.average_wave(p.point.z, p.dpdx.z, p.dpdy.z);
+
+        let even = (1.0 + wave) / 2.0;
+        if even >= 1.0 {
+            self.even.evaluate(p)
+        } else if even <= 0.0 {
+            self.odd.evaluate(p)
+        } else {
+            self.even.evaluate(p).scale(even) + self.odd.evaluate(p).scale(1.0 - even)
+        }
+    }
+}
+
+// origin から origin + direction にかけて from から to へ変化する
+pub struct Gradient {
+    pub from: Arc<dyn Texture>,
+    pub to: Arc<dyn Texture>,
+    pub origin: Vector3,
+    pub direction: Vector3,
+}
+
+impl Texture for Gradient {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        let len = self.direction.dot(&self.direction);
+        let t = ((p.point - self.origin).dot(&self.direction) / len).clamp(0.0, 1.0);
+        self.from.evaluate(p).scale(1.0 - t) + self.to.evaluate(p).scale(t)
+    }
+}
+
+// 範囲外の UV の扱い
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(&self, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m < n {
+                    m
+                } else {
+                    2 * n - 1 - m
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    Trilinear, // レイの微分から MIP レベルを選んで補間する
+}
+
+struct MipLevel {
+    width: u32,
+    height: u32,
+    pixels: Vec<Spectrum>,
+    alpha: Vec<f64>,
+}
+
+impl MipLevel {
+    // 2x2 の平均で半分の解像度にする
+    fn downsample(&self) -> Self {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        let mut alpha = Vec::with_capacity(pixels.capacity());
+
+        for y in 0..height {
+            for x in 0..width {
+                let mut color = BLACK;
+                let mut a = 0.0;
+                for &(sx, sy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                    let sx = (2 * x + sx).min(self.width - 1);
+                    let sy = (2 * y + sy).min(self.height - 1);
+                    let i = (sy * self.width + sx) as usize;
+                    color += self.pixels[i].scale(0.25);
+                    a += self.alpha[i] * 0.25;
+                }
+                pixels.push(color);
+                alpha.push(a);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            alpha,
+        }
+    }
+}
+
+// UV で引く画像 (v = 0 が画像の上端)
+pub struct ImageTexture {
+    levels: Vec<MipLevel>,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+impl ImageTexture {
+    fn new(base: MipLevel, filter: Filter) -> Self {
+        let mut levels = vec![base];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+
+        Self {
+            levels,
+            wrap: WrapMode::Repeat,
+            filter,
+        }
+    }
+
+    pub fn from_fn(width: u32, height: u32, f: impl Fn(u32, u32) -> Spectrum) -> Self {
+        let pixels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| f(x, y))
+            .collect();
+
+        let base = MipLevel {
+            width,
+            height,
+            pixels,
+            alpha: vec![1.0; (width * height) as usize],
+        };
+        Self::new(base, Filter::Nearest)
+    }
+
+    // PNG を読み込む。色として使う画像は srgb を true にしてリニアに変換する
+    pub fn load(path: impl AsRef<Path>, srgb: bool) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let (info, mut reader) = decoder.read_info()?;
+
+        let mut buf = vec![0; info.buffer_size()];
+        reader.next_frame(&mut buf)?;
+
+        let samples: Vec<f64> = match info.bit_depth {
+            png::BitDepth::Sixteen => buf
+                .chunks_exact(2)
+                .map(|x| u16::from_be_bytes([x[0], x[1]]) as f64 / 65535.0)
+                .collect(),
+            _ => buf.iter().map(|&x| x as f64 / 255.0).collect(),
+        };
+
+        let channels = info.color_type.samples();
+        let decode = |c: f64| if srgb { srgb_to_linear(c) } else { c };
+
+        let mut pixels = Vec::with_capacity((info.width * info.height) as usize);
+        let mut alpha = Vec::with_capacity(pixels.capacity());
+        for px in samples.chunks_exact(channels) {
+            // グレースケールは 1 チャンネル、アルファは (あれば) 最後のチャンネル
+            let (r, g, b) = if channels < 3 {
+                (px[0], px[0], px[0])
+            } else {
+                (px[0], px[1], px[2])
+            };
+            pixels.push(Spectrum {
+                r: decode(r),
+                g: decode(g),
+                b: decode(b),
+            });
+            alpha.push(if channels % 2 == 0 {
+                px[channels - 1]
+            } else {
+                1.0
+            });
+        }
+
+        let base = MipLevel {
+            width: info.width,
+            height: info.height,
+            pixels,
+            alpha,
+        };
+        Ok(Self::new(base, Filter::Trilinear))
+    }
+
+    fn texel(&self, level: &MipLevel, x: i64, y: i64) -> (Spectrum, f64) {
+        let x = self.wrap.apply(x, level.width);
+        let y = self.wrap.apply(y, level.height);
+        let i = (y * level.width + x) as usize;
+        (level.pixels[i], level.alpha[i])
+    }
+
+    fn bilinear(&self, level: &MipLevel, u: f64, v: f64) -> (Spectrum, f64) {
+        let x = u * level.width as f64 - 0.5;
+        let y = v * level.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let mut color = BLACK;
+        let mut alpha = 0.0;
+        for &(dx, dy, w) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ]
+        .iter()
+        {
+            let (c, a) = self.texel(level, x0 + dx, y0 + dy);
+            color += c.scale(w);
+            alpha += a * w;
+        }
+
+        (color, alpha)
+    }
+
+    fn lookup(&self, p: &TexturePoint) -> (Spectrum, f64) {
+        let base = &self.levels[0];
+
+        match self.filter {
+            Filter::Nearest => {
+                let x = (p.u * base.width as f64).floor();
+                let y = (p.v * base.height as f64).floor();
+                self.texel(base, x as i64, y as i64)
+            }
+            Filter::Bilinear => self.bilinear(base, p.u, p.v),
+            Filter::Trilinear => {
+                // 1 ピクセルがテクセル何個分に広がるか
+                let (w, h) = (base.width as f64, base.height as f64);
+                let width = f64::max(
+                    (p.dudx * w).hypot(p.dvdx * h),
+                    (p.dudy * w).hypot(p.dvdy * h),
+                );
+                let lod = width
+                    .max(1e-8)
+                    .log2()
+                    .clamp(0.0, (self.levels.len() - 1) as f64);
+
+                let lo = lod.floor() as usize;
+                let hi = (lo + 1).min(self.levels.len() - 1);
+                let t = lod - lod.floor();
+
+                let (c0, a0) = self.bilinear(&self.levels[lo], p.u, p.v);
+                let (c1, a1) = self.bilinear(&self.levels[hi], p.u, p.v);
+                (c0.scale(1.0 - t) + c1.scale(t), a0 * (1.0 - t) + a1 * t)
+            }
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        self.lookup(p).0
+    }
+
+    fn alpha(&self, p: &TexturePoint) -> f64 {
+        self.lookup(p).1
+    }
+}
+
+// 平面に投影した座標で内側のテクスチャを引く
+pub struct PlanarMapping {
+    pub texture: Arc<dyn Texture>,
+    pub origin: Vector3,
+    pub u_direction: Vector3,
+    pub v_direction: Vector3,
+    pub texture_size: f64,
+}
+
+impl Texture for PlanarMapping {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        let d = p.point - self.origin;
+        let u = |d: Vector3| d.dot(&self.u_direction) / self.texture_size;
+        let v = |d: Vector3| -d.dot(&self.v_direction) / self.texture_size;
+
+        self.texture.evaluate(&TexturePoint {
+            u: u(d),
+            v: v(d),
+            dudx: u(p.dpdx),
+            dvdx: v(p.dpdx),
+            dudy: u(p.dpdy),
+            dvdy: v(p.dpdy),
+            ..*p
+        })
+    }
+}
+
+// 3 つの軸に沿った平面投影を法線の向きに応じて混ぜる (UV のない形状向け)
+pub struct TriplanarMapping {
+    pub texture: Arc<dyn Texture>,
+    pub origin: Vector3,
+    pub texture_size: f64,
+    pub sharpness: f64, // 大きいほど投影の境目がはっきりする
+}
+
+impl TriplanarMapping {
+    // 各投影の重みと、その投影で引いた点
+    fn projections(&self, p: &TexturePoint) -> [(f64, TexturePoint); 3] {
+        let n = p.normal;
+        let w = [n.x, n.y, n.z].map(|c| c.abs().powf(self.sharpness));
+        let sum = w[0] + w[1] + w[2];
+
+        let d = p.point - self.origin;
+        let s = 1.0 / self.texture_size;
+        let project = |c: fn(Vector3) -> (f64, f64)| {
+            let (u, v) = c(d);
+            let (dudx, dvdx) = c(p.dpdx);
+            let (dudy, dvdy) = c(p.dpdy);
+            TexturePoint {
+                u: u * s,
+                v: v * s,
+                dudx: dudx * s,
+                dvdx: dvdx * s,
+                dudy: dudy * s,
+                dvdy: dvdy * s,
+                ..*p
+            }
+        };
+
+        // 画像の上が +y (y 軸方向の投影では -z) になるように向きをそろえる
+        [
+            (w[0] / sum, project(|d| (d.z, -d.y))),
+            (w[1] / sum, project(|d| (d.x, d.z))),
+            (w[2] / sum, project(|d| (d.x, -d.y))),
+        ]
+    }
+}
+
+impl Texture for TriplanarMapping {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        self.projections(p)
+            .iter()
+            .filter(|(w, _)| *w > 0.0)
+            .fold(BLACK, |acc, (w, q)| {
+                acc + self.texture.evaluate(q).scale(*w)
+            })
+    }
+
+    fn alpha(&self, p: &TexturePoint) -> f64 {
+        self.projections(p)
+            .iter()
+            .filter(|(w, _)| *w > 0.0)
+            .map(|(w, q)| self.texture.alpha(q) * w)
+            .sum()
+    }
+}
+
+// factor が 0 なら a、1 なら b
+pub struct Mix {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+    pub factor: Arc<dyn Texture>,
+}
+
+impl Texture for Mix {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        let t = self.factor.value(p);
+        self.a.evaluate(p).scale(1.0 - t) + self.b.evaluate(p).scale(t)
+    }
+}
+
+// 内側のテクスチャのアルファを値として使う (不透明度のチャンネル向け)
+pub struct Alpha {
+    pub texture: Arc<dyn Texture>,
+}
+
+impl Texture for Alpha {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        let a = self.texture.alpha(p);
+        Spectrum { r: a, g: a, b: a }
+    }
+}
+
+pub struct Multiply {
+    pub a: Arc<dyn Texture>,
+    pub b: Arc<dyn Texture>,
+}
+
+impl Texture for Multiply {
+    fn evaluate(&self, p: &TexturePoint) -> Spectrum {
+        self.a.evaluate(p) * self.b.evaluate(p)
+    }
+}
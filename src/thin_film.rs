@@ -0,0 +1,154 @@
+use crate::spectrum::Spectrum;
+use std::f64::consts::PI;
+use std::ops::{Add, Div, Mul, Sub};
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn real(re: f64) -> Self {
+        Self { re, im: 0.0 }
+    }
+
+    fn norm2(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn sqrt(self) -> Self {
+        let r = self.norm2().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        Self {
+            re,
+            im: if self.im < 0.0 { -im } else { im },
+        }
+    }
+
+    // e^(i θ)
+    fn phase(theta: Complex) -> Self {
+        let m = (-theta.im).exp();
+        Self {
+            re: m * theta.re.cos(),
+            im: m * theta.re.sin(),
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, o: Self) -> Self {
+        Self {
+            re: self.re + o.re,
+            im: self.im + o.im,
+        }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, o: Self) -> Self {
+        Self {
+            re: self.re - o.re,
+            im: self.im - o.im,
+        }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, o: Self) -> Self {
+        Self {
+            re: self.re * o.re - self.im * o.im,
+            im: self.re * o.im + self.im * o.re,
+        }
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, o: Self) -> Self {
+        let d = o.norm2();
+        Self {
+            re: (self.re * o.re + self.im * o.im) / d,
+            im: (self.im * o.re - self.re * o.im) / d,
+        }
+    }
+}
+
+// RGB モードで各チャンネルの反射率を平均する波長 [nm]
+const CHANNEL_WAVELENGTHS: [[f64; 4]; 3] = [
+    [610.0, 635.0, 660.0, 685.0],
+    [510.0, 535.0, 560.0, 585.0],
+    [410.0, 435.0, 460.0, 485.0],
+];
+
+// 表面を覆う薄膜 (シャボン玉や油膜の干渉色)
+#[derive(Clone, Copy)]
+pub struct ThinFilm {
+    pub thickness: f64, // 膜の厚さ [nm]
+    pub ior: f64,
+}
+
+impl ThinFilm {
+    // 屈折率 outside の媒質から入射し、膜の下の基材の複素屈折率が eta + ik のときの反射率
+    fn reflectance_at(&self, cos: f64, outside: f64, eta: f64, k: f64, wavelength: f64) -> f64 {
+        let one = Complex::real(1.0);
+        let n0 = Complex::real(outside);
+        let n1 = Complex::real(self.ior);
+        let n2 = Complex { re: eta, im: k };
+
+        let cos0 = Complex::real(cos.clamp(0.0, 1.0));
+        let sin2 = Complex::real(1.0 - cos0.re * cos0.re);
+        let cos1 = (one - sin2 * (n0 / n1) * (n0 / n1)).sqrt();
+        let cos2 = (one - sin2 * (n0 / n2) * (n0 / n2)).sqrt();
+
+        // 膜の上面と下面での振幅反射率 (s 偏光と p 偏光)
+        let r01s = (n0 * cos0 - n1 * cos1) / (n0 * cos0 + n1 * cos1);
+        let r01p = (n1 * cos0 - n0 * cos1) / (n1 * cos0 + n0 * cos1);
+        let r12s = (n1 * cos1 - n2 * cos2) / (n1 * cos1 + n2 * cos2);
+        let r12p = (n2 * cos1 - n1 * cos2) / (n2 * cos1 + n1 * cos2);
+
+        // 膜の中を往復したことによる位相差
+        let delta = Complex::real(4.0 * PI * self.thickness / wavelength) * n1 * cos1;
+        let e = Complex::phase(delta);
+
+        let airy = |r01: Complex, r12: Complex| ((r01 + r12 * e) / (one + r01 * r12 * e)).norm2();
+        (0.5 * (airy(r01s, r12s) + airy(r01p, r12p))).clamp(0.0, 1.0)
+    }
+
+    // スペクトルモードではその波長での値を全チャンネルに入れる
+    pub fn reflectance(
+        &self,
+        cos: f64,
+        outside: f64,
+        eta: Spectrum,
+        k: Spectrum,
+        wavelength: Option<f64>,
+    ) -> Spectrum {
+        if let Some(w) = wavelength {
+            let r = self.reflectance_at(cos, outside, eta.r, k.r, w);
+            return Spectrum { r, g: r, b: r };
+        }
+
+        let channel = |i: usize, eta: f64, k: f64| {
+            let ws = &CHANNEL_WAVELENGTHS[i];
+            ws.iter()
+                .map(|&w| self.reflectance_at(cos, outside, eta, k, w))
+                .sum::<f64>()
+                / ws.len() as f64
+        };
+
+        Spectrum {
+            r: channel(0, eta.r, k.r),
+            g: channel(1, eta.g, k.g),
+            b: channel(2, eta.b, k.b),
+        }
+    }
+}
@@ -68,7 +68,7 @@ impl Vector3 {
     }
 
     pub fn reflect(&self, normal: &Self) -> Self {
-        *self - normal.scale(2.0 * self.dot(&normal))
+        *self - normal.scale(2.0 * self.dot(normal))
     }
 
     pub fn len(&self) -> f64 {
@@ -100,6 +100,26 @@ impl Vector3 {
         }
     }
 
+    // 自身(正規化済み)と直交する二つの単位ベクトル
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let helper = if self.x.abs() > 0.9 {
+            Vector3 {
+                x: 0.0,
+                y: 1.0,
+                z: 0.0,
+            }
+        } else {
+            Vector3 {
+                x: 1.0,
+                y: 0.0,
+                z: 0.0,
+            }
+        };
+        let t = self.cross(helper).normalize();
+        let b = self.cross(t);
+        (t, b)
+    }
+
     pub fn random_hemisphere(&self) -> Self {
         loop {
             let mut dir = Vector3 {
@@ -0,0 +1,392 @@
+use crate::medium::{henyey_greenstein, sample_henyey_greenstein};
+use crate::random;
+use crate::ray::Ray;
+use crate::spectrum::{self, Spectrum, BLACK};
+use crate::vector::Vector3;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+const BLACKBODY_STEP: f64 = 50.0;
+
+// 密度グリッドで表される非一様な媒質 (煙・雲・炎)
+//
+// ファイル形式: リトルエンディアンの u32 で nx, ny, nz、
+// 続いて nx * ny * nz 個の f32 (x が最も速く変化する)
+pub struct GridVolume {
+    pub min: Vector3,
+    pub max: Vector3,
+    pub extinction: f64, // 密度 1 あたりの σt
+    pub albedo: Spectrum,
+    pub anisotropy: f64,
+    pub emission: Spectrum,
+    size: [usize; 3],
+    density: Vec<f32>,
+    max_density: f64,
+    emission_grid: Option<Vec<Spectrum>>,
+}
+
+fn read_grid(path: impl AsRef<Path>) -> io::Result<([usize; 3], Vec<f32>)> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut read_u32 = || -> io::Result<u32> {
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    };
+    let size = [
+        read_u32()? as usize,
+        read_u32()? as usize,
+        read_u32()? as usize,
+    ];
+
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+
+    let count = size[0]
+        .checked_mul(size[1])
+        .and_then(|n| n.checked_mul(size[2]))
+        .filter(|&n| n > 0);
+    if count.and_then(|n| n.checked_mul(4)) != Some(bytes.len()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("grid size {:?} does not match {} bytes", size, bytes.len()),
+        ));
+    }
+
+    let values = bytes
+        .chunks_exact(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect();
+
+    Ok((size, values))
+}
+
+impl GridVolume {
+    pub fn load(path: impl AsRef<Path>, min: Vector3, max: Vector3) -> io::Result<Self> {
+        let (size, density) = read_grid(path)?;
+        let max_density = density.iter().fold(0.0f32, |a, &b| a.max(b)) as f64;
+
+        Ok(Self {
+            min,
+            max,
+            extinction: 1.0,
+            albedo: Spectrum {
+                r: 0.8,
+                g: 0.8,
+                b: 0.8,
+            },
+            anisotropy: 0.0,
+            emission: BLACK,
+            size,
+            density,
+            max_density,
+            emission_grid: None,
+        })
+    }
+
+    // 温度グリッド (ケルビン) を読み込み、黒体放射の色で発光させる。
+    // 明るさは最高温度に対する比の 4 乗に比例し、intensity 倍される。
+    pub fn load_temperature(&mut self, path: impl AsRef<Path>, intensity: f64) -> io::Result<()> {
+        let (size, temperature) = read_grid(path)?;
+        if size != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "temperature grid size differs from density grid",
+            ));
+        }
+
+        let max_temperature = temperature.iter().fold(0.0f32, |a, &b| a.max(b)) as f64;
+        let table: Vec<Spectrum> = (0..=(max_temperature / BLACKBODY_STEP).ceil() as usize)
+            .map(|i| spectrum::blackbody(i as f64 * BLACKBODY_STEP))
+            .collect();
+
+        self.emission_grid = Some(
+            temperature
+                .iter()
+                .map(|&t| {
+                    let t = (t as f64).max(0.0);
+                    let color = table[(t / BLACKBODY_STEP).round() as usize];
+                    color.scale(intensity * (t / max_temperature.max(1.0)).powi(4))
+                })
+                .collect(),
+        );
+
+        Ok(())
+    }
+
+    fn bounds(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let mut near = 0.0f64;
+        let mut far = f64::INFINITY;
+
+        let axes = [
+            (ray.origin.x, ray.dir.x, self.min.x, self.max.x),
+            (ray.origin.y, ray.dir.y, self.min.y, self.max.y),
+            (ray.origin.z, ray.dir.z, self.min.z, self.max.z),
+        ];
+
+        for (origin, dir, min, max) in axes.iter() {
+            let a = (min - origin) / dir;
+            let b = (max - origin) / dir;
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+
+        if near < far {
+            Some((near, far))
+        } else {
+            None
+        }
+    }
+
+    // 三線形補間に使う 8 つの格子点とその重み
+    fn corners(&self, point: Vector3) -> [(usize, f64); 8] {
+        let p = [
+            (point.x - self.min.x) / (self.max.x - self.min.x),
+            (point.y - self.min.y) / (self.max.y - self.min.y),
+            (point.z - self.min.z) / (self.max.z - self.min.z),
+        ];
+
+        let mut lo = [0; 3];
+        let mut hi = [0; 3];
+        let mut f = [0.0; 3];
+        for i in 0..3 {
+            let x = (p[i] * self.size[i] as f64 - 0.5).max(0.0);
+            let n = self.size[i] - 1;
+            lo[i] = (x.floor() as usize).min(n);
+            hi[i] = (lo[i] + 1).min(n);
+            f[i] = (x - x.floor()).min(1.0);
+        }
+
+        let mut result = [(0, 0.0); 8];
+        for (i, corner) in result.iter_mut().enumerate() {
+            let pick = |axis: usize| {
+                if i >> axis & 1 == 0 {
+                    (lo[axis], 1.0 - f[axis])
+                } else {
+                    (hi[axis], f[axis])
+                }
+            };
+            let (x, wx) = pick(0);
+            let (y, wy) = pick(1);
+            let (z, wz) = pick(2);
+            *corner = (x + self.size[0] * (y + self.size[1] * z), wx * wy * wz);
+        }
+
+        result
+    }
+
+    pub fn density(&self, point: Vector3) -> f64 {
+        self.corners(point)
+            .iter()
+            .map(|&(i, w)| self.density[i] as f64 * w)
+            .sum()
+    }
+
+    pub fn emission(&self, point: Vector3) -> Spectrum {
+        match &self.emission_grid {
+            Some(grid) => self
+                .corners(point)
+                .iter()
+                .fold(BLACK, |acc, &(i, w)| acc + grid[i].scale(w)),
+            None => self.emission,
+        }
+    }
+
+    // デルタトラッキングで実衝突までの距離をサンプリングする
+    pub fn sample_collision(&self, ray: &Ray, max_distance: f64) -> Option<f64> {
+        let (near, far) = self.bounds(ray)?;
+        let far = far.min(max_distance);
+        let majorant = self.max_density * self.extinction;
+
+        if majorant <= 0.0 {
+            return None;
+        }
+
+        let mut t = near;
+        loop {
+            t -= (1.0 - random(0.0, 1.0)).ln() / majorant;
+            if far <= t {
+                return None;
+            }
+
+            let density = self.density(ray.origin + ray.dir.scale(t));
+            if random(0.0, 1.0) < density * self.extinction / majorant {
+                return Some(t);
+            }
+        }
+    }
+
+    // レシオトラッキングで透過率を推定する
+    pub fn transmittance(&self, ray: &Ray, max_distance: f64) -> f64 {
+        let (near, far) = match self.bounds(ray) {
+            Some(b) => b,
+            None => return 1.0,
+        };
+        let far = far.min(max_distance);
+        let majorant = self.max_density * self.extinction;
+
+        if majorant <= 0.0 {
+            return 1.0;
+        }
+
+        let mut t = near;
+        let mut tr = 1.0;
+        loop {
+            t -= (1.0 - random(0.0, 1.0)).ln() / majorant;
+            if far <= t {
+                return tr;
+            }
+
+            let density = self.density(ray.origin + ray.dir.scale(t));
+            tr *= 1.0 - density * self.extinction / majorant;
+        }
+    }
+
+    pub fn phase(&self, dir: Vector3, scattered: Vector3) -> f64 {
+        henyey_greenstein(self.anisotropy, dir.dot(&scattered))
+    }
+
+    pub fn sample_phase(&self, dir: Vector3) -> Vector3 {
+        sample_henyey_greenstein(self.anisotropy, dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // テスト用の一時ファイル (使い終わったら消す)
+    struct TempFile(std::path::PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!("{}-{}.grid", name, std::process::id()));
+            std::fs::write(&path, bytes).unwrap();
+            TempFile(path)
+        }
+    }
+
+    impl AsRef<Path> for TempFile {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn grid_file(name: &str, size: [u32; 3], values: &[f32]) -> TempFile {
+        let mut bytes = vec![];
+        for n in size.iter() {
+            bytes.extend_from_slice(&n.to_le_bytes());
+        }
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        TempFile::new(name, &bytes)
+    }
+
+    fn unit_box() -> (Vector3, Vector3) {
+        (
+            Vector3::default(),
+            Vector3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        )
+    }
+
+    fn point(x: f64, y: f64, z: f64) -> Vector3 {
+        Vector3 { x, y, z }
+    }
+
+    #[test]
+    fn load_reads_dimensions_and_values() {
+        let values: Vec<f32> = (0..24).map(|i| i as f32).collect();
+        let path = grid_file("dims", [2, 3, 4], &values);
+        let (min, max) = unit_box();
+
+        let volume = GridVolume::load(&path, min, max).unwrap();
+        assert_eq!(volume.size, [2, 3, 4]);
+        assert_eq!(volume.density, values);
+        assert_eq!(volume.max_density, 23.0);
+    }
+
+    #[test]
+    fn load_rejects_bad_sizes() {
+        let (min, max) = unit_box();
+
+        let short = grid_file("short", [2, 2, 2], &[1.0; 7]);
+        let err = GridVolume::load(&short, min, max).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let empty = grid_file("empty", [0, 2, 2], &[]);
+        let err = GridVolume::load(&empty, min, max).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // 要素数が usize に収まらない
+        let huge = grid_file("huge", [u32::MAX; 3], &[1.0; 4]);
+        let err = GridVolume::load(&huge, min, max).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let header = TempFile::new("header", &[1, 0, 0, 0, 1, 0]);
+        let err = GridVolume::load(&header, min, max).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        let density = grid_file("density", [2, 2, 2], &[1.0; 8]);
+        let temperature = grid_file("temperature", [2, 2, 1], &[1000.0; 4]);
+        let mut volume = GridVolume::load(&density, min, max).unwrap();
+        let err = volume.load_temperature(&temperature, 1.0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn density_is_trilinear_between_voxel_centers() {
+        let path = grid_file("lerp", [2, 1, 1], &[0.0, 1.0]);
+        let volume = GridVolume::load(&path, Vector3::default(), point(2.0, 1.0, 1.0)).unwrap();
+
+        assert!((volume.density(point(0.5, 0.5, 0.5)) - 0.0).abs() < 1e-12);
+        assert!((volume.density(point(1.0, 0.5, 0.5)) - 0.5).abs() < 1e-12);
+        assert!((volume.density(point(1.25, 0.5, 0.5)) - 0.75).abs() < 1e-12);
+        assert!((volume.density(point(1.5, 0.5, 0.5)) - 1.0).abs() < 1e-12);
+
+        // 端の格子点より外は端の値になる
+        assert!((volume.density(point(0.1, 0.5, 0.5)) - 0.0).abs() < 1e-12);
+        assert!((volume.density(point(1.9, 0.5, 0.5)) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn transmittance_matches_beer_lambert_for_homogeneous_grid() {
+        let path = grid_file("homogeneous", [4, 4, 4], &[0.5; 64]);
+        let (min, max) = unit_box();
+        let mut volume = GridVolume::load(&path, min, max).unwrap();
+        volume.extinction = 1.4;
+
+        let ray = Ray {
+            origin: point(-1.0, 0.5, 0.5),
+            dir: point(1.0, 0.0, 0.0),
+            differentials: None,
+        };
+        let n = 100_000;
+        for &(max_distance, length) in [(10.0, 1.0f64), (1.5, 0.5)].iter() {
+            let mean = (0..n)
+                .map(|_| volume.transmittance(&ray, max_distance))
+                .sum::<f64>()
+                / n as f64;
+            let expected = (-0.5 * 1.4 * length).exp();
+            assert!((mean - expected).abs() < 0.01, "{} != {}", mean, expected);
+        }
+
+        // 箱に当たらないレイは減衰しない
+        let miss = Ray {
+            origin: point(-1.0, 2.0, 0.5),
+            ..ray
+        };
+        assert_eq!(volume.transmittance(&miss, 10.0), 1.0);
+    }
+}